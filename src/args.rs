@@ -0,0 +1,195 @@
+use std::path::Path;
+
+pub const USAGE: &str = "\
+Usage: ele [OPTION]... [+LINE[:COL]] [FILE[:LINE[:COL]]]...
+With FILE of -, read standard input. With several FILEs, C-x Left and
+C-x Right switch between them.
+
+Options:
+  -R, --read-only  open the files in read-only mode
+  -h, --help       display this help and exit
+  -V, --version    output version information and exit
+  --               treat all following arguments as files
+
+Environment:
+  ELE_AMBIGUOUS_WIDTH
+                   width of East Asian ambiguous characters: 1 (default),
+                   2, or auto to measure it on the terminal; C-x a
+                   toggles it while editing
+  ELE_ESCDELAY     milliseconds to wait for the rest of an escape sequence
+                   after ESC (default 100)
+";
+
+pub enum Command {
+    Edit(Args),
+    Help,
+    Version,
+}
+
+#[derive(Default)]
+pub struct Args {
+    pub files: Vec<FileArg>,
+    pub read_only: bool,
+}
+
+pub struct FileArg {
+    // None for standard input
+    pub filename: Option<String>,
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+}
+
+impl Command {
+    pub fn parse<I: Iterator<Item = String>>(iter: I) -> Result<Self, String> {
+        let mut args = Args::default();
+        let mut position = None;
+        let mut options_ended = false;
+        let mut stdin_used = false;
+
+        for arg in iter {
+            if options_ended {
+                args.files.push(FileArg::new(Some(arg), position.take()));
+                continue;
+            }
+            match arg.as_str() {
+                "--" => options_ended = true,
+                "-" if stdin_used => {
+                    return Err("standard input can only be read once".into());
+                }
+                "-" => {
+                    args.files.push(FileArg::new(None, position.take()));
+                    stdin_used = true;
+                }
+                "-R" | "--read-only" => args.read_only = true,
+                "-h" | "--help" => return Ok(Self::Help),
+                "-V" | "--version" => return Ok(Self::Version),
+                s if s.starts_with('+') => match parse_line_col(&s[1..]) {
+                    Some(line_col) => position = Some(line_col),
+                    None => return Err(format!("invalid position '{}'", s)),
+                },
+                s if s.starts_with('-') && s.len() > 1 => {
+                    return Err(format!("unknown option '{}'", s));
+                }
+                _ => args.files.push(FileArg::parse(arg, position.take())),
+            }
+        }
+
+        if let Some((line, _)) = position {
+            return Err(format!("'+{}' must be followed by a file", line));
+        }
+        Ok(Self::Edit(args))
+    }
+}
+
+impl FileArg {
+    fn new(filename: Option<String>, position: Option<(usize, Option<usize>)>) -> Self {
+        Self {
+            filename,
+            line: position.map(|(line, _)| line),
+            col: position.and_then(|(_, col)| col),
+        }
+    }
+
+    // Accepts "FILE:LINE" and "FILE:LINE:COL" as printed by compilers
+    fn parse(arg: String, position: Option<(usize, Option<usize>)>) -> Self {
+        if position.is_some() || Path::new(&arg).exists() {
+            return Self::new(Some(arg), position);
+        }
+
+        let s = arg.trim_end_matches(':');
+
+        let parts: Vec<&str> = s.rsplitn(3, ':').collect();
+        if let [col, line, filename] = parts[..] {
+            if let (Ok(line), Ok(col)) = (line.parse(), col.parse()) {
+                if !filename.is_empty() {
+                    return Self::new(Some(filename.to_string()), Some((line, Some(col))));
+                }
+            }
+        }
+
+        let parts: Vec<&str> = s.rsplitn(2, ':').collect();
+        if let [line, filename] = parts[..] {
+            if let Ok(line) = line.parse() {
+                if !filename.is_empty() {
+                    return Self::new(Some(filename.to_string()), Some((line, None)));
+                }
+            }
+        }
+
+        Self::new(Some(arg), None)
+    }
+}
+
+// "LINE" or "LINE:COL"
+pub fn parse_line_col(s: &str) -> Option<(usize, Option<usize>)> {
+    let mut parts = s.splitn(2, ':');
+    let line = parts.next()?.parse().ok()?;
+    match parts.next() {
+        Some(col) => Some((line, Some(col.parse().ok()?))),
+        None => Some((line, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // e.g. "-R <stdin> +3 a.rs +1:2" for stdin at line 3 and a.rs at 1:2
+    fn parse(args: &[&str]) -> Result<String, String> {
+        let args = match Command::parse(args.iter().map(|s| s.to_string()))? {
+            Command::Edit(args) => args,
+            Command::Help => return Ok("help".into()),
+            Command::Version => return Ok("version".into()),
+        };
+        let mut words = Vec::new();
+        if args.read_only {
+            words.push("-R".to_string());
+        }
+        for file in args.files {
+            words.push(file.filename.unwrap_or_else(|| "<stdin>".into()));
+            match (file.line, file.col) {
+                (Some(line), Some(col)) => words.push(format!("+{}:{}", line, col)),
+                (Some(line), None) => words.push(format!("+{}", line)),
+                _ => (),
+            }
+        }
+        Ok(words.join(" "))
+    }
+
+    #[test]
+    fn parse_command_line() {
+        let cases: &[(&[&str], Result<&str, &str>)] = &[
+            (&[], Ok("")),
+            (&["a"], Ok("a")),
+            (&["-h", "a"], Ok("help")),
+            (&["a", "--version"], Ok("version")),
+            (&["-R", "a"], Ok("-R a")),
+            (&["a", "--read-only"], Ok("-R a")),
+            (&["-", "a"], Ok("<stdin> a")),
+            (&["-", "-"], Err("standard input can only be read once")),
+            (&["--", "-R", "-", "-"], Ok("-R - -")),
+            (&["-", "--", "-"], Ok("<stdin> -")),
+            (&["+3", "a"], Ok("a +3")),
+            (&["+3:4", "-"], Ok("<stdin> +3:4")),
+            (&["+3", "--", "-R"], Ok("-R +3")),
+            (&["+3", "a:5"], Ok("a:5 +3")),
+            (&["a:3:4"], Ok("a +3:4")),
+            (&["a:3:4:"], Ok("a +3:4")),
+            (&["a:3:"], Ok("a +3")),
+            (&["a:b:3"], Ok("a:b +3")),
+            (&[":3"], Ok(":3")),
+            (&["a:x"], Ok("a:x")),
+            (&["src/args.rs:1"], Ok("src/args.rs +1")),
+            (&["-x"], Err("unknown option '-x'")),
+            (&["--read_only"], Err("unknown option '--read_only'")),
+            (&["+x", "a"], Err("invalid position '+x'")),
+            (&["+3:", "a"], Err("invalid position '+3:'")),
+            (&["+3"], Err("'+3' must be followed by a file")),
+            (&["a", "+3:4"], Err("'+3' must be followed by a file")),
+        ];
+        for (args, expected) in cases {
+            let expected = expected.map(String::from).map_err(String::from);
+            assert_eq!(parse(args), expected, "{:?}", args);
+        }
+    }
+}
@@ -1,17 +1,27 @@
 use std::cmp;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
 use crate::canvas::Canvas;
 use crate::coord::{Pos, Size};
+use crate::dired::{Dired, Entry};
 use crate::event::Event;
-use crate::face::{Bg, Fg};
-use crate::key::Key;
-use crate::row::Row;
+use crate::face::{Bg, Fg, Overlay};
+use crate::gutter::{Gutter, LineNumbers};
+use crate::key::{Key, Mod};
+use crate::row::{self, Row};
 use crate::rows::{Rows, RowsMethods};
 use crate::syntax::Syntax;
 use crate::util::DrawRange;
 
+// files at least this large are read on demand, see Rows::from_file
+const LARGE_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+// the error of any edit of a read-only buffer
+struct ReadOnly;
+
 #[derive(Default)]
 struct Search {
     matches: Vec<Match>,
@@ -20,21 +30,39 @@ struct Search {
     orig_cursor: Pos,
 }
 
+// the undo history with byte indices in place of columns
+pub struct History {
+    events: Vec<Event>,
+    redo_len: usize,
+    cursor: Pos,
+    anchor: Option<Pos>,
+}
+
 struct Match {
     pos: Pos,
-    faces: Vec<(Fg, Bg)>,
+    end_x: usize,
 }
 
 pub struct Buffer {
     syntax: Box<dyn Syntax>,
     pub filename: Option<String>,
+    name: &'static str,
+    pub read_only: bool,
+    // a part of the file could not be read
+    unreadable: bool,
+    pub dired: Option<Dired>,
     pos: Pos,
     size: Size,
     offset: Pos,
     cursor: Pos,
     anchor: Option<Pos>,
+    // the region was started by a shifted motion
+    shift_region: bool,
     saved_x: usize,
     rows: Rows,
+    gutter: Gutter,
+    wrap: bool,
+    cursor_line: bool,
     draw_range: DrawRange,
     undo: bool,
     undo_list: Vec<Event>,
@@ -50,13 +78,21 @@ impl Buffer {
         let mut buffer = Self {
             syntax: <dyn Syntax>::detect(filename.as_deref()),
             filename,
+            name: "newfile",
+            read_only: false,
+            unreadable: false,
+            dired: None,
             pos: Pos::new(0, 0),
             size: Size::new(0, 0),
             offset: Pos::new(0, 0),
             cursor: Pos::new(0, 0),
             anchor: None,
+            shift_region: false,
             saved_x: 0,
             rows: Rows::new(),
+            gutter: Gutter::new(),
+            wrap: false,
+            cursor_line: false,
             draw_range: Default::default(),
             undo: false,
             undo_list: Vec::new(),
@@ -70,23 +106,30 @@ impl Buffer {
         Ok(buffer)
     }
 
+    pub fn from_reader<R: BufRead>(name: &'static str, reader: R) -> io::Result<Self> {
+        let mut buffer = Self::new(None)?;
+        buffer.name = name;
+        buffer.rows.clear();
+        buffer.load(reader)?;
+        buffer.syntax_update(0);
+        Ok(buffer)
+    }
+
     fn init(&mut self) -> io::Result<()> {
         if let Some(filename) = self.filename.as_deref() {
+            if Path::new(filename).is_dir() {
+                let dired = Dired::read(Path::new(filename))?;
+                self.set_dired(dired, None);
+                return Ok(());
+            }
             let file = File::open(filename)?;
-            let mut reader = BufReader::new(file);
-            let mut buf = String::new();
-
-            let crlf: &[_] = &['\r', '\n'];
-            let mut ends_with_lf = false;
-
-            while reader.read_line(&mut buf)? > 0 {
-                let string = buf.trim_end_matches(crlf).to_string();
-                self.rows.push(Row::new(string));
-                ends_with_lf = buf.ends_with('\n');
-                buf.clear();
+            if let Err(e) = OpenOptions::new().write(true).open(filename) {
+                self.read_only = e.kind() == io::ErrorKind::PermissionDenied;
             }
-            if self.rows.is_empty() || ends_with_lf {
-                self.rows.push(Row::new(String::new()));
+            if file.metadata()?.len() >= LARGE_FILE_SIZE {
+                self.rows = Rows::from_file(file)?;
+            } else {
+                self.load(BufReader::new(file))?;
             }
         } else {
             self.rows.push(Row::new(String::new()));
@@ -96,17 +139,39 @@ impl Buffer {
         Ok(())
     }
 
+    fn load<R: BufRead>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut buf = String::new();
+
+        let mut rows = Vec::new();
+
+        let crlf: &[_] = &['\r', '\n'];
+        let mut ends_with_lf = false;
+
+        while reader.read_line(&mut buf)? > 0 {
+            let string = buf.trim_end_matches(crlf).to_string();
+            rows.push(Row::new(string));
+            ends_with_lf = buf.ends_with('\n');
+            buf.clear();
+        }
+        if rows.is_empty() || ends_with_lf {
+            rows.push(Row::new(String::new()));
+        }
+        self.rows = rows.into_iter().collect();
+        Ok(())
+    }
+
     pub fn save(&mut self) -> io::Result<()> {
         if let Some(filename) = self.filename.as_deref() {
-            let file = File::create(filename)?;
-            let mut writer = BufWriter::new(file);
-            let len = self.rows.len();
+            if self.rows.is_lazy() {
+                // the saved file is indexed again, as the old offsets are gone
+                let path = write_in_place(filename, |tmp| self.write_file(tmp))?;
+                self.rows = Rows::from_file(File::open(path)?)?;
+            } else {
+                self.write_file(filename)?;
+            }
 
-            for (i, row) in self.rows.iter_mut().enumerate() {
-                writer.write(row.string.as_bytes())?;
-                if i < len - 1 {
-                    writer.write(b"\n")?;
-                }
+            // unread rows are highlighted when they are drawn
+            for row in self.rows.iter_loaded_mut() {
                 row.context = None;
             }
 
@@ -120,6 +185,30 @@ impl Buffer {
         Ok(())
     }
 
+    // writes modified contents next to the original file, e.g. on SIGHUP
+    pub fn emergency_save(&self) -> io::Result<()> {
+        if !self.modified() {
+            return Ok(());
+        }
+        let filename = match self.filename.as_deref() {
+            Some(filename) => format!("{}.ele-save", filename),
+            None => format!("{}.{}.ele-save", self.name.trim_matches('*'), process::id()),
+        };
+        self.write_file(&filename)
+    }
+
+    fn write_file<P: AsRef<Path>>(&self, filename: P) -> io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+        self.rows.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    // whether the file is too large to be read at once
+    pub fn is_lazy(&self) -> bool {
+        self.rows.is_lazy()
+    }
+
     pub fn modified(&self) -> bool {
         self.saved_eid != self.undo_list.last().map(|e| e.id())
     }
@@ -131,13 +220,161 @@ impl Buffer {
         self.draw_range.full_expand();
     }
 
+    pub fn update_mappings(&mut self) {
+        // unread leaves are mapped when read
+        for row in self.rows.iter_loaded_mut() {
+            row.update_mappings();
+        }
+        self.cursor.x = self.rows[self.cursor.y].prev_fit_x(self.cursor.x);
+        self.saved_x = self.cursor.x;
+        if let Some(anchor) = self.anchor.as_mut() {
+            anchor.x = self.rows[anchor.y].prev_fit_x(anchor.x);
+        }
+        self.offset.x = 0;
+        self.draw_range.full_expand();
+    }
+
+    // undoes every edit, so that the history can be replayed once the
+    // columns change
+    pub fn unwind_history(&mut self) -> History {
+        let cursor = self.pos_to_idx(self.cursor);
+        let anchor = self.anchor.map(|pos| self.pos_to_idx(pos));
+        let redo_len = self.redo_list.len();
+        while let Some(event) = self.redo_list.pop() {
+            let revent = self.apply_event(event);
+            self.undo_list.push(revent);
+        }
+
+        let mut events = Vec::new();
+        while let Some(event) = self.undo_list.pop() {
+            let revent = self.apply_event(event);
+            events.push(revent.map_pos(|pos| self.pos_to_idx(pos)));
+        }
+
+        History {
+            events,
+            redo_len,
+            cursor,
+            anchor,
+        }
+    }
+
+    pub fn replay_history(&mut self, history: History) {
+        for event in history.events.into_iter().rev() {
+            let event = event.map_pos(|pos| self.idx_to_pos(pos));
+            let revent = self.apply_event(event);
+            self.undo_list.push(revent);
+        }
+        for _ in 0..history.redo_len {
+            let event = self.undo_list.pop().unwrap();
+            let revent = self.apply_event(event);
+            self.redo_list.push(revent);
+        }
+
+        self.cursor = self.idx_to_pos(history.cursor);
+        self.saved_x = self.cursor.x;
+        self.anchor = history.anchor.map(|pos| self.idx_to_pos(pos));
+        self.clear_matches(false);
+        self.scroll();
+        self.draw_range.full_expand();
+    }
+
+    fn pos_to_idx(&self, pos: Pos) -> Pos {
+        let row = &self.rows[pos.y];
+        Pos::new(row.x_to_idx(row.prev_fit_x(pos.x)), pos.y)
+    }
+
+    fn idx_to_pos(&self, pos: Pos) -> Pos {
+        Pos::new(self.rows[pos.y].idx_to_x(pos.x), pos.y)
+    }
+
+    // makes the buffer read-only once a part of the file could not be
+    // read, returning the message to show the first time
+    pub fn check_unreadable(&mut self) -> Option<String> {
+        if self.unreadable {
+            return None;
+        }
+        let message = format!(
+            "Cannot read file: {}; buffer is read-only",
+            self.rows.error()?
+        );
+        self.unreadable = true;
+        self.read_only = true;
+        Some(message)
+    }
+
+    pub fn toggle_read_only(&mut self) -> &'static str {
+        if self.dired.is_some() {
+            return "Directory listing is read-only";
+        }
+        if self.unreadable {
+            return "File could not be read";
+        }
+        self.read_only = !self.read_only;
+        if self.read_only {
+            "Read-only mode enabled"
+        } else {
+            "Read-only mode disabled"
+        }
+    }
+
+    pub fn redraw(&mut self) {
+        self.draw_range.full_expand();
+    }
+
+    pub fn toggle_line_numbers(&mut self) -> &'static str {
+        let message = self.gutter.toggle();
+        self.scroll();
+        self.draw_range.full_expand();
+        message
+    }
+
+    pub fn toggle_wrap(&mut self) -> &'static str {
+        self.wrap = !self.wrap;
+        self.offset.x = 0;
+        self.last_key = None;
+        self.scroll();
+        self.draw_range.full_expand();
+        if self.wrap {
+            "Visual line mode enabled"
+        } else {
+            "Visual line mode disabled"
+        }
+    }
+
+    pub fn toggle_cursor_line(&mut self) -> &'static str {
+        self.cursor_line = !self.cursor_line;
+        self.draw_range.expand(self.cursor.y, self.cursor.y + 1);
+        if self.cursor_line {
+            "Cursor line highlighting enabled"
+        } else {
+            "Cursor line highlighting disabled"
+        }
+    }
+
     pub fn draw(&mut self, canvas: &mut Canvas) -> io::Result<()> {
-        if let Some((start, end)) = self.draw_range.as_tuple() {
+        self.update_gutter();
+        self.highlight_visible();
+
+        if self.wrap && self.draw_range.as_tuple().is_some() {
+            let size = Size::new(self.wrap_width(), self.size.h);
+
+            canvas.set_cursor(self.pos.x, self.pos.y)?;
+            self.rows
+                .draw_wrapped(canvas, self.offset, size, &self.gutter, &|y| {
+                    self.overlays(y)
+                })?;
+
+            self.draw_range.clear();
+        } else if let Some((start, end)) = self.draw_range.as_tuple() {
             let y_range = start.max(self.offset.y)..end.min(self.offset.y + self.size.h);
-            let x_range = self.offset.x..(self.offset.x + self.size.w);
+            let x_range = self.offset.x..(self.offset.x + self.text_width());
 
             canvas.set_cursor(self.pos.x, self.pos.y + y_range.start - self.offset.y)?;
-            self.rows.draw(canvas, x_range, y_range)?;
+            self.rows
+                .draw(canvas, x_range, y_range, &self.gutter, &|y| {
+                    self.overlays(y)
+                })?;
 
             self.draw_range.clear();
         }
@@ -146,9 +383,41 @@ impl Buffer {
         self.draw_status_bar(canvas)
     }
 
+    fn update_gutter(&mut self) {
+        let width = self.gutter.width_for(self.rows.len());
+        if width != self.gutter.width {
+            self.gutter.width = width;
+            self.draw_range.full_expand();
+        }
+
+        if self.cursor.y != self.gutter.cursor_y {
+            match self.gutter.line_numbers {
+                LineNumbers::Off if !self.cursor_line => (),
+                LineNumbers::Off | LineNumbers::Absolute => {
+                    let y = self.gutter.cursor_y;
+                    self.draw_range.expand(y, y + 1);
+                    self.draw_range.expand(self.cursor.y, self.cursor.y + 1);
+                }
+                LineNumbers::Relative => self.draw_range.full_expand(),
+            }
+            self.gutter.cursor_y = self.cursor.y;
+        }
+    }
+
+    fn text_width(&self) -> usize {
+        self.size
+            .w
+            .saturating_sub(self.gutter.width_for(self.rows.len()))
+    }
+
     fn draw_status_bar(&self, canvas: &mut Canvas) -> io::Result<()> {
-        let filename = self.filename.as_deref().unwrap_or("newfile");
-        let modified = if self.modified() { "+" } else { "" };
+        let filename = self.filename.as_deref().unwrap_or(self.name);
+        let modified = match (self.read_only, self.modified()) {
+            (true, true) => "%+",
+            (true, false) => "%",
+            (false, true) => "+",
+            (false, false) => "",
+        };
         let cursor = format!("{}, {}", self.cursor.y + 1, self.cursor.x + 1);
         let syntax = self.syntax.name();
 
@@ -186,18 +455,61 @@ impl Buffer {
     }
 
     pub fn draw_cursor(&self, canvas: &mut Canvas) -> io::Result<()> {
+        if self.wrap {
+            let line = self.screen_line_start(self.cursor);
+            let y = self.screen_distance(self.offset, line, self.size.h);
+            return canvas.set_cursor(
+                self.pos.x + self.gutter.width + self.cursor.x - line.x,
+                self.pos.y + y,
+            );
+        }
         canvas.set_cursor(
-            self.pos.x + self.cursor.x - self.offset.x,
+            self.pos.x + self.gutter.width + self.cursor.x - self.offset.x,
             self.pos.y + self.cursor.y - self.offset.y,
         )
     }
 
     pub fn process_key(&mut self, key: Key, clipboard: &mut String) -> &str {
-        let message = match key {
-            Key::ArrowLeft | Key::Ctrl(b'B') => {
+        // view mode: page with SPC/DEL like less
+        let key = match key {
+            Key::Char(' ') if self.read_only => Key::PageDown(Mod::NONE),
+            Key::Backspace if self.read_only => Key::PageUp(Mod::NONE),
+            key => key,
+        };
+
+        match key.motion_mod() {
+            Some(m) if m.contains(Mod::SHIFT) && self.anchor.is_none() => {
+                self.anchor = Some(self.cursor);
+                self.shift_region = true;
+            }
+            Some(m) if m.contains(Mod::SHIFT) => (),
+            Some(_) if self.shift_region => {
+                if let Some(anchor) = self.anchor.take() {
+                    self.redraw_region(anchor);
+                }
+                self.shift_region = false;
+            }
+            _ => (),
+        }
+
+        match self.run_command(&key, clipboard) {
+            Ok(message) => {
+                self.last_key = Some(key);
+                message
+            }
+            Err(ReadOnly) => {
+                self.last_key = None;
+                "Buffer is read-only"
+            }
+        }
+    }
+
+    fn run_command(&mut self, key: &Key, clipboard: &mut String) -> Result<&'static str, ReadOnly> {
+        let message = match *key {
+            Key::ArrowLeft(Mod::NONE | Mod::SHIFT) | Key::Ctrl(b'B') => {
                 if let Some(pos) = self.rows.prev_pos(self.cursor) {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
                     self.saved_x = pos.x;
@@ -205,10 +517,10 @@ impl Buffer {
                 }
                 ""
             }
-            Key::ArrowRight | Key::Ctrl(b'F') => {
+            Key::ArrowRight(Mod::NONE | Mod::SHIFT) | Key::Ctrl(b'F') => {
                 if let Some(pos) = self.rows.next_pos(self.cursor) {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
                     self.saved_x = pos.x;
@@ -216,94 +528,76 @@ impl Buffer {
                 }
                 ""
             }
-            Key::ArrowUp | Key::Ctrl(b'P') => {
-                if self.cursor.y > 0 {
-                    let pos = Pos::new(
-                        self.rows[self.cursor.y - 1].prev_fit_x(self.saved_x),
-                        self.cursor.y - 1,
-                    );
+            Key::ArrowUp(_) | Key::Ctrl(b'P') => {
+                if let Some(pos) = self.prev_line_pos() {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
                     self.scroll();
                 }
                 ""
             }
-            Key::ArrowDown | Key::Ctrl(b'N') => {
-                if self.cursor.y < self.rows.len() - 1 {
-                    let pos = Pos::new(
-                        self.rows[self.cursor.y + 1].prev_fit_x(self.saved_x),
-                        self.cursor.y + 1,
-                    );
+            Key::ArrowDown(_) | Key::Ctrl(b'N') => {
+                if let Some(pos) = self.next_line_pos() {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
                     self.scroll();
                 }
                 ""
             }
-            Key::Home | Key::Ctrl(b'A') => {
+            Key::Home(Mod::NONE | Mod::SHIFT) | Key::Ctrl(b'A') => {
                 let x = self.rows[self.cursor.y].indent_width();
                 let pos = Pos::new(if self.cursor.x == x { 0 } else { x }, self.cursor.y);
                 if self.anchor.is_some() {
-                    self.highlight_region(pos);
+                    self.redraw_region(pos);
                 }
                 self.cursor = pos;
                 self.saved_x = pos.x;
                 self.scroll();
                 ""
             }
-            Key::End | Key::Ctrl(b'E') => {
+            Key::End(Mod::NONE | Mod::SHIFT) | Key::Ctrl(b'E') => {
                 let pos = Pos::new(self.rows[self.cursor.y].last_x(), self.cursor.y);
                 if self.anchor.is_some() {
-                    self.highlight_region(pos);
+                    self.redraw_region(pos);
                 }
                 self.cursor = pos;
                 self.saved_x = pos.x;
                 self.scroll();
                 ""
             }
-            Key::PageUp | Key::Alt(b'v') => {
-                if self.offset.y > 0 {
-                    let delta = cmp::min(self.size.h, self.offset.y);
-                    let pos = Pos::new(
-                        self.rows[self.cursor.y - delta].prev_fit_x(self.saved_x),
-                        self.cursor.y - delta,
-                    );
+            Key::PageUp(_) | Key::Alt(b'v') => {
+                if let Some((pos, offset)) = self.prev_page_pos() {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
-                    self.offset.y -= delta;
+                    self.offset = offset;
                     self.draw_range.full_expand();
                 }
                 ""
             }
-            Key::PageDown | Key::Ctrl(b'V') => {
-                if self.offset.y + self.size.h < self.rows.len() {
-                    let delta = cmp::min(self.size.h, self.rows.len() - 1 - self.cursor.y);
-                    let pos = Pos::new(
-                        self.rows[self.cursor.y + delta].prev_fit_x(self.saved_x),
-                        self.cursor.y + delta,
-                    );
+            Key::PageDown(_) | Key::Ctrl(b'V') => {
+                if let Some((pos, offset)) = self.next_page_pos() {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
-                    self.offset.y += self.size.h;
+                    self.offset = offset;
                     self.draw_range.full_expand();
                 }
                 ""
             }
             Key::Backspace | Key::Ctrl(b'H') => {
                 if let Some(anchor) = self.anchor {
-                    self.remove_region(anchor);
+                    self.remove_region(anchor)?;
                     self.anchor = None;
                 } else if let Some(pos) = self.rows.prev_pos(self.cursor) {
                     let event = Event::RemoveMv(self.eid(), pos, self.cursor);
-                    let revent = self.process_event(event);
+                    let revent = self.process_event(event)?;
                     if let Some(Key::Backspace | Key::Ctrl(b'H')) = self.last_key {
                         self.merge_event(revent);
                     } else {
@@ -313,14 +607,14 @@ impl Buffer {
                 }
                 ""
             }
-            Key::Delete | Key::Ctrl(b'D') => {
+            Key::Delete(_) | Key::Ctrl(b'D') => {
                 if let Some(anchor) = self.anchor {
-                    self.remove_region(anchor);
+                    self.remove_region(anchor)?;
                     self.anchor = None;
                 } else if let Some(pos) = self.rows.next_pos(self.cursor) {
                     let event = Event::Remove(self.eid(), self.cursor, pos);
-                    let revent = self.process_event(event);
-                    if let Some(Key::Delete | Key::Ctrl(b'D')) = self.last_key {
+                    let revent = self.process_event(event)?;
+                    if let Some(Key::Delete(_) | Key::Ctrl(b'D')) = self.last_key {
                         self.merge_event(revent);
                     } else {
                         self.push_event(revent);
@@ -330,29 +624,30 @@ impl Buffer {
             }
             Key::Ctrl(b'@') => {
                 if let Some(anchor) = self.anchor {
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                 }
                 self.anchor = Some(self.cursor);
+                self.shift_region = false;
                 "Mark set"
             }
             Key::Ctrl(b'G') => {
                 if let Some(anchor) = self.anchor {
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                 }
                 self.anchor = None;
                 "Quit"
             }
-            Key::Ctrl(b'I') => {
+            Key::Tab(Mod::NONE) => {
                 if let Some(unit) = self.syntax.indent_unit() {
                     if let Some(anchor) = self.anchor {
-                        self.unhighlight_region(anchor);
-                        self.indent_region(anchor, unit);
+                        self.redraw_region(anchor);
+                        self.indent_region(anchor, unit)?;
                         self.anchor = None;
                     } else {
                         let string = unit.repeat(self.rows[self.cursor.y].indent_level);
                         if self.rows[self.cursor.y].indent_part() != string {
                             let event = Event::Indent(self.eid(), self.cursor, string);
-                            let revent = self.process_event(event);
+                            let revent = self.process_event(event)?;
                             self.push_event(revent);
                         } else {
                             let x = self.rows[self.cursor.y].indent_width();
@@ -364,12 +659,12 @@ impl Buffer {
                     }
                 } else {
                     if let Some(anchor) = self.anchor {
-                        self.remove_region(anchor);
+                        self.remove_region(anchor)?;
                         self.anchor = None;
                     }
                     let event = Event::InsertMv(self.eid(), self.cursor, "\t".into());
-                    let revent = self.process_event(event);
-                    if let Some(Key::Ctrl(b'I')) = self.last_key {
+                    let revent = self.process_event(event)?;
+                    if let Some(Key::Tab(Mod::NONE)) = self.last_key {
                         self.merge_event(revent);
                     } else {
                         self.push_event(revent);
@@ -378,13 +673,13 @@ impl Buffer {
                 self.scroll();
                 ""
             }
-            Key::Ctrl(b'J' | b'M') => {
+            Key::Enter(_) | Key::Ctrl(b'J') => {
                 if let Some(anchor) = self.anchor {
-                    self.remove_region(anchor);
+                    self.remove_region(anchor)?;
                     self.anchor = None;
                 }
 
-                let eid = if let Some(Key::Ctrl(b'J' | b'M')) = self.last_key {
+                let eid = if let Some(Key::Enter(_) | Key::Ctrl(b'J')) = self.last_key {
                     self.undo_list.last().unwrap().id()
                 } else {
                     self.eid()
@@ -393,27 +688,27 @@ impl Buffer {
                 if self.cursor.x <= self.rows[self.cursor.y].indent_width() {
                     if !self.rows[self.cursor.y].indent_part().is_empty() {
                         let event = Event::Indent(eid, self.cursor, "".into());
-                        let revent = self.process_event(event);
+                        let revent = self.process_event(event)?;
                         self.push_event(revent);
                     }
                 } else if let Some(unit) = self.syntax.indent_unit() {
                     let string = unit.repeat(self.rows[self.cursor.y].indent_level);
                     if self.rows[self.cursor.y].indent_part() != string {
                         let event = Event::Indent(eid, self.cursor, string);
-                        let revent = self.process_event(event);
+                        let revent = self.process_event(event)?;
                         self.push_event(revent);
                     }
                 }
 
                 let event = Event::InsertMv(eid, self.cursor, "\n".into());
-                let revent = self.process_event(event);
+                let revent = self.process_event(event)?;
                 self.push_event(revent);
 
                 if let Some(unit) = self.syntax.indent_unit() {
                     let string = unit.repeat(self.rows[self.cursor.y].indent_level);
                     if self.rows[self.cursor.y].indent_part() != string {
                         let event = Event::Indent(eid, self.cursor, string);
-                        let revent = self.process_event(event);
+                        let revent = self.process_event(event)?;
                         self.push_event(revent);
                     }
                 }
@@ -422,27 +717,27 @@ impl Buffer {
             }
             Key::Ctrl(b'K') => {
                 if let Some(anchor) = self.anchor {
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                     self.anchor = None;
                 }
                 let pos = Pos::new(self.rows[self.cursor.y].last_x(), self.cursor.y);
                 clipboard.clear();
                 clipboard.push_str(&self.rows.read_str(self.cursor, pos));
                 let event = Event::Remove(self.eid(), self.cursor, pos);
-                let revent = self.process_event(event);
+                let revent = self.process_event(event)?;
                 self.push_event(revent);
                 ""
             }
             Key::Ctrl(b'U') => {
                 if let Some(anchor) = self.anchor {
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                     self.anchor = None;
                 }
                 let pos = Pos::new(0, self.cursor.y);
                 clipboard.clear();
                 clipboard.push_str(&self.rows.read_str(pos, self.cursor));
                 let event = Event::RemoveMv(self.eid(), pos, self.cursor);
-                let revent = self.process_event(event);
+                let revent = self.process_event(event)?;
                 self.push_event(revent);
                 self.scroll();
                 ""
@@ -451,27 +746,29 @@ impl Buffer {
                 if let Some(anchor) = self.anchor {
                     clipboard.clear();
                     clipboard.push_str(&self.read_region(anchor));
-                    self.remove_region(anchor);
+                    self.remove_region(anchor)?;
                     self.anchor = None;
                 }
                 ""
             }
             Key::Ctrl(b'Y') => {
                 if let Some(anchor) = self.anchor {
-                    self.remove_region(anchor);
+                    self.remove_region(anchor)?;
                     self.anchor = None;
                 }
                 let event = Event::InsertMv(self.eid(), self.cursor, clipboard.clone());
-                let revent = self.process_event(event);
+                let revent = self.process_event(event)?;
                 self.push_event(revent);
                 self.scroll();
                 ""
             }
             Key::Ctrl(b'_') => {
                 if let Some(anchor) = self.anchor {
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                     self.anchor = None;
                 }
+                // before anything is popped off the history
+                self.check_writable()?;
                 if !matches!(self.last_key, Some(Key::Ctrl(b'_'))) {
                     self.undo = !self.undo;
                 }
@@ -479,7 +776,7 @@ impl Buffer {
                     if let Some(eid) = self.undo_list.last().map(|e| e.id()) {
                         while self.undo_list.last().map_or(false, |e| e.id() == eid) {
                             let event = self.undo_list.pop().unwrap();
-                            let revent = self.process_event(event);
+                            let revent = self.process_event(event)?;
                             self.redo_list.push(revent);
                         }
                         self.scroll_center();
@@ -491,7 +788,7 @@ impl Buffer {
                     if let Some(eid) = self.redo_list.last().map(|e| e.id()) {
                         while self.redo_list.last().map_or(false, |e| e.id() == eid) {
                             let event = self.redo_list.pop().unwrap();
-                            let revent = self.process_event(event);
+                            let revent = self.process_event(event)?;
                             self.undo_list.push(revent);
                         }
                         self.scroll_center();
@@ -501,30 +798,30 @@ impl Buffer {
                     }
                 }
             }
-            Key::Alt(b'<') => {
+            Key::Alt(b'<') | Key::Home(_) => {
                 let pos = Pos::new(0, 0);
                 if self.anchor.is_some() {
-                    self.highlight_region(pos);
+                    self.redraw_region(pos);
                 }
                 self.cursor = pos;
                 self.saved_x = pos.x;
                 self.scroll();
                 ""
             }
-            Key::Alt(b'>') => {
+            Key::Alt(b'>') | Key::End(_) => {
                 let pos = self.rows.last_pos();
                 if self.anchor.is_some() {
-                    self.highlight_region(pos);
+                    self.redraw_region(pos);
                 }
                 self.cursor = pos;
                 self.saved_x = pos.x;
                 self.scroll();
                 ""
             }
-            Key::Alt(b'b') => {
+            Key::Alt(b'b') | Key::ArrowLeft(_) => {
                 if let Some(pos) = self.rows.prev_word_pos(self.cursor) {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
                     self.saved_x = pos.x;
@@ -534,20 +831,20 @@ impl Buffer {
             }
             Key::Alt(b'd') => {
                 if let Some(anchor) = self.anchor {
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                     self.anchor = None;
                 }
                 if let Some(pos) = self.rows.next_word_pos(self.cursor) {
                     let event = Event::Remove(self.eid(), self.cursor, pos);
-                    let revent = self.process_event(event);
+                    let revent = self.process_event(event)?;
                     self.push_event(revent);
                 }
                 ""
             }
-            Key::Alt(b'f') => {
+            Key::Alt(b'f') | Key::ArrowRight(_) => {
                 if let Some(pos) = self.rows.next_word_pos(self.cursor) {
                     if self.anchor.is_some() {
-                        self.highlight_region(pos);
+                        self.redraw_region(pos);
                     }
                     self.cursor = pos;
                     self.saved_x = pos.x;
@@ -557,12 +854,12 @@ impl Buffer {
             }
             Key::Alt(b'h') => {
                 if let Some(anchor) = self.anchor {
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                     self.anchor = None;
                 }
                 if let Some(pos) = self.rows.prev_word_pos(self.cursor) {
                     let event = Event::RemoveMv(self.eid(), pos, self.cursor);
-                    let revent = self.process_event(event);
+                    let revent = self.process_event(event)?;
                     self.push_event(revent);
                     self.scroll();
                 }
@@ -572,18 +869,18 @@ impl Buffer {
                 if let Some(anchor) = self.anchor {
                     clipboard.clear();
                     clipboard.push_str(&self.read_region(anchor));
-                    self.unhighlight_region(anchor);
+                    self.redraw_region(anchor);
                     self.anchor = None;
                 }
                 ""
             }
             Key::Char(ch) => {
                 if let Some(anchor) = self.anchor {
-                    self.remove_region(anchor);
+                    self.remove_region(anchor)?;
                     self.anchor = None;
                 }
                 let event = Event::InsertMv(self.eid(), self.cursor, ch.to_string());
-                let revent = self.process_event(event);
+                let revent = self.process_event(event)?;
                 if let Some(Key::Char(_)) = self.last_key {
                     self.merge_event(revent);
                 } else {
@@ -594,13 +891,107 @@ impl Buffer {
             }
             _ => "",
         };
+        Ok(message)
+    }
 
-        self.last_key = Some(key);
+    pub fn goto_line(&mut self, line: usize, col: usize) {
+        let y = cmp::min(line.saturating_sub(1), self.rows.len() - 1);
+        let x = self.rows[y].char_x(col.saturating_sub(1));
+        self.goto(Pos::new(x, y));
+    }
 
-        message
+    pub fn goto_char(&mut self, n: usize) {
+        match self.rows.find_char(n.saturating_sub(1)) {
+            Some((y, i)) => {
+                let x = self.rows[y].char_x(i);
+                self.goto(Pos::new(x, y));
+            }
+            None => self.goto(self.rows.last_pos()),
+        }
     }
 
-    fn process_event(&mut self, event: Event) -> Event {
+    pub fn describe_char(&self) -> String {
+        if self.cursor == self.rows.last_pos() {
+            return "End of buffer".into();
+        }
+
+        let row = &self.rows[self.cursor.y];
+        let idx = row.x_to_idx(self.cursor.x);
+        let ch = row.string[idx..].chars().next().unwrap_or('\n');
+        let offset = self.rows.byte_offset(self.cursor.y) + idx;
+
+        let mut bytes = [0; 4];
+        let utf8: Vec<_> = ch
+            .encode_utf8(&mut bytes)
+            .bytes()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        let width = row::char_width(ch).map_or("none".into(), |w| w.to_string());
+        let (fg, bg) = Overlay::merge(
+            &self.overlays(self.cursor.y),
+            self.cursor.x,
+            row.faces.get(idx),
+        );
+
+        format!(
+            "Char: {} (U+{:04X}) UTF-8: {} width: {} byte: {} in row, {} in file face: {:?}/{:?}",
+            ch,
+            ch as u32,
+            utf8.join(" "),
+            width,
+            idx,
+            offset,
+            fg,
+            bg
+        )
+    }
+
+    // for quoted insertion of characters that cannot be typed
+    pub fn insert_char(&mut self, ch: char) -> &'static str {
+        self.last_key = None;
+        match self.insert_str(&ch.to_string()) {
+            Ok(()) => "",
+            Err(ReadOnly) => "Buffer is read-only",
+        }
+    }
+
+    fn insert_str(&mut self, string: &str) -> Result<(), ReadOnly> {
+        if let Some(anchor) = self.anchor {
+            self.remove_region(anchor)?;
+            self.anchor = None;
+        }
+        let event = Event::InsertMv(self.eid(), self.cursor, string.into());
+        let revent = self.process_event(event)?;
+        self.push_event(revent);
+        self.scroll();
+        Ok(())
+    }
+
+    fn goto(&mut self, pos: Pos) {
+        if self.anchor.is_some() {
+            self.redraw_region(pos);
+        }
+        self.cursor = pos;
+        self.saved_x = pos.x;
+        self.last_key = None;
+        self.scroll_center();
+    }
+
+    fn check_writable(&self) -> Result<(), ReadOnly> {
+        if self.read_only {
+            Err(ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    // every edit goes through here, so that read-only buffers reject them
+    fn process_event(&mut self, event: Event) -> Result<Event, ReadOnly> {
+        self.check_writable()?;
+        Ok(self.apply_event(event))
+    }
+
+    fn apply_event(&mut self, event: Event) -> Event {
         match event {
             Event::Insert(id, pos1, string) => {
                 let pos2 = self.rows.insert_str(pos1, &string);
@@ -679,17 +1070,53 @@ impl Buffer {
     }
 
     fn syntax_update(&mut self, y: usize) {
-        let len = self.syntax.update_rows(&mut self.rows[y..]);
+        // large files are highlighted no further than the screen, and
+        // the row after that is left to highlight_visible
+        let limit = if self.rows.is_lazy() {
+            cmp::max(y + 1, self.offset.y + self.size.h) - y
+        } else {
+            usize::MAX
+        };
+
+        // unlike other rows, the first one has nothing above to highlight
+        if y == 0 && self.rows[0].context.is_none() {
+            self.rows[0].context = Some(String::new());
+        }
+
+        let len = self
+            .syntax
+            .update_rows(&mut self.rows.iter_mut_from(y).take(limit));
+        if len == limit && y + len < self.rows.len() {
+            self.rows[y + len].context = None;
+        }
+
         self.draw_range.expand(y, y + len);
     }
 
+    // highlights rows scrolled into view, continuing from the row above
+    fn highlight_visible(&mut self) {
+        let end = cmp::min(self.offset.y + self.size.h, self.rows.len());
+        if let Some(y) = (self.offset.y..end).find(|&y| self.rows[y].context.is_none()) {
+            if y > 0 && self.rows[y - 1].context.is_some() {
+                self.syntax_update(y - 1);
+            } else {
+                self.syntax_update(y);
+            }
+        }
+    }
+
     fn scroll(&mut self) {
+        if self.wrap {
+            return self.scroll_wrapped();
+        }
+
+        let width = self.text_width();
         if self.cursor.x < self.offset.x {
             self.offset.x = self.cursor.x;
             self.draw_range.full_expand();
         }
-        if self.cursor.x >= self.offset.x + self.size.w {
-            self.offset.x = self.cursor.x - self.size.w + 1;
+        if self.cursor.x >= self.offset.x + width {
+            self.offset.x = self.cursor.x - width + 1;
             self.draw_range.full_expand();
         }
         if self.cursor.y < self.offset.y {
@@ -703,8 +1130,13 @@ impl Buffer {
     }
 
     fn scroll_center(&mut self) {
-        if self.cursor.x < self.offset.x || self.cursor.x >= self.offset.x + self.size.w {
-            self.offset.x = self.cursor.x.saturating_sub(self.size.w / 2);
+        if self.wrap {
+            return self.scroll_center_wrapped();
+        }
+
+        let width = self.text_width();
+        if self.cursor.x < self.offset.x || self.cursor.x >= self.offset.x + width {
+            self.offset.x = self.cursor.x.saturating_sub(width / 2);
             self.draw_range.full_expand();
         }
         if self.cursor.y < self.offset.y || self.cursor.y >= self.offset.y + self.size.h {
@@ -715,57 +1147,260 @@ impl Buffer {
 }
 
 impl Buffer {
-    fn read_region(&self, anchor: Pos) -> String {
-        let pos1 = self.cursor.min(anchor);
-        let pos2 = self.cursor.max(anchor);
-        self.rows.read_str(pos1, pos2)
+    fn prev_line_pos(&mut self) -> Option<Pos> {
+        if self.wrap {
+            let col = self.goal_col();
+            let line = self.prev_screen_line(self.screen_line_start(self.cursor))?;
+            Some(Pos::new(self.screen_line_x(line, col), line.y))
+        } else if self.cursor.y > 0 {
+            let y = self.cursor.y - 1;
+            Some(Pos::new(self.rows[y].prev_fit_x(self.saved_x), y))
+        } else {
+            None
+        }
     }
 
-    fn highlight_region(&mut self, pos: Pos) {
-        let pos1 = self.cursor.min(pos);
-        let pos2 = self.cursor.max(pos);
+    fn next_line_pos(&mut self) -> Option<Pos> {
+        if self.wrap {
+            let col = self.goal_col();
+            let line = self.next_screen_line(self.screen_line_start(self.cursor))?;
+            Some(Pos::new(self.screen_line_x(line, col), line.y))
+        } else if self.cursor.y < self.rows.len() - 1 {
+            let y = self.cursor.y + 1;
+            Some(Pos::new(self.rows[y].prev_fit_x(self.saved_x), y))
+        } else {
+            None
+        }
+    }
 
-        for y in pos1.y..=pos2.y {
-            let row = &mut self.rows[y];
-            let x1 = if y == pos1.y { pos1.x } else { 0 };
-            let x2 = if y == pos2.y { pos2.x } else { row.last_x() };
-
-            for i in row.x_to_idx(x1)..row.x_to_idx(x2) {
-                row.faces[i].1 = match row.faces[i].1 {
-                    Bg::Default => Bg::Region,
-                    _ => Bg::Default,
-                };
+    fn prev_page_pos(&mut self) -> Option<(Pos, Pos)> {
+        if self.wrap {
+            let col = self.goal_col();
+            let mut line = self.screen_line_start(self.cursor);
+            let mut offset = self.prev_screen_line(self.offset)?;
+            for _ in 1..self.size.h {
+                match self.prev_screen_line(offset) {
+                    Some(pos) => offset = pos,
+                    None => break,
+                }
             }
-            if y < pos2.y {
-                row.trailing_bg = match row.trailing_bg {
-                    Bg::Default => Bg::Region,
-                    _ => Bg::Default,
-                };
+            while line >= offset && self.screen_distance(offset, line, self.size.h) >= self.size.h {
+                line = self.prev_screen_line(line)?;
+            }
+            Some((Pos::new(self.screen_line_x(line, col), line.y), offset))
+        } else if self.offset.y > 0 {
+            let delta = cmp::min(self.size.h, self.offset.y);
+            let y = self.cursor.y - delta;
+            let pos = Pos::new(self.rows[y].prev_fit_x(self.saved_x), y);
+            Some((pos, Pos::new(self.offset.x, self.offset.y - delta)))
+        } else {
+            None
+        }
+    }
+
+    fn next_page_pos(&mut self) -> Option<(Pos, Pos)> {
+        if self.wrap {
+            let col = self.goal_col();
+            let mut line = self.screen_line_start(self.cursor);
+            let mut offset = self.offset;
+            for _ in 0..self.size.h {
+                offset = self.next_screen_line(offset)?;
+            }
+            while line < offset {
+                match self.next_screen_line(line) {
+                    Some(pos) => line = pos,
+                    None => break,
+                }
+            }
+            Some((Pos::new(self.screen_line_x(line, col), line.y), offset))
+        } else if self.offset.y + self.size.h < self.rows.len() {
+            let delta = cmp::min(self.size.h, self.rows.len() - 1 - self.cursor.y);
+            let y = self.cursor.y + delta;
+            let pos = Pos::new(self.rows[y].prev_fit_x(self.saved_x), y);
+            Some((pos, Pos::new(self.offset.x, self.offset.y + self.size.h)))
+        } else {
+            None
+        }
+    }
+
+    // In visual line mode, saved_x holds the column within the screen line
+    fn goal_col(&mut self) -> usize {
+        if !matches!(
+            self.last_key,
+            Some(
+                Key::ArrowUp(_)
+                    | Key::ArrowDown(_)
+                    | Key::PageUp(_)
+                    | Key::PageDown(_)
+                    | Key::Ctrl(b'N' | b'P' | b'V')
+                    | Key::Alt(b'v')
+            )
+        ) {
+            self.saved_x = self.cursor.x - self.screen_line_start(self.cursor).x;
+        }
+        self.saved_x
+    }
+
+    fn wrap_width(&self) -> usize {
+        self.text_width().saturating_sub(1).max(1)
+    }
+
+    // the wrap points start with 0 and are cached by the rows
+    fn screen_line_start(&self, pos: Pos) -> Pos {
+        let xs = self.rows[pos.y].wrap_xs(self.wrap_width());
+        let i = xs.partition_point(|&x| x <= pos.x);
+        Pos::new(xs[i - 1], pos.y)
+    }
+
+    fn prev_screen_line(&self, line: Pos) -> Option<Pos> {
+        let xs = self.rows[line.y].wrap_xs(self.wrap_width());
+        let i = xs.partition_point(|&x| x < line.x);
+        if i > 0 {
+            Some(Pos::new(xs[i - 1], line.y))
+        } else if line.y > 0 {
+            let xs = self.rows[line.y - 1].wrap_xs(self.wrap_width());
+            Some(Pos::new(xs[xs.len() - 1], line.y - 1))
+        } else {
+            None
+        }
+    }
+
+    fn next_screen_line(&self, line: Pos) -> Option<Pos> {
+        let xs = self.rows[line.y].wrap_xs(self.wrap_width());
+        if let Some(&x) = xs.get(xs.partition_point(|&x| x <= line.x)) {
+            Some(Pos::new(x, line.y))
+        } else if line.y < self.rows.len() - 1 {
+            Some(Pos::new(0, line.y + 1))
+        } else {
+            None
+        }
+    }
+
+    fn screen_line_x(&self, line: Pos, col: usize) -> usize {
+        let row = &self.rows[line.y];
+        let end_x = match self.next_screen_line(line) {
+            Some(pos) if pos.y == line.y => pos.x - 1,
+            _ => row.last_x(),
+        };
+        row.prev_fit_x(cmp::min(line.x + col, end_x))
+    }
+
+    fn screen_distance(&self, line1: Pos, line2: Pos, limit: usize) -> usize {
+        let mut line = line1;
+        let mut n = 0;
+        while line < line2 && n < limit {
+            match self.next_screen_line(line) {
+                Some(pos) => line = pos,
+                None => break,
             }
+            n += 1;
         }
-        self.draw_range.expand(pos1.y, pos2.y + 1);
+        n
     }
 
-    fn unhighlight_region(&mut self, anchor: Pos) {
+    fn screen_lines_before(&self, line: Pos, n: usize) -> Pos {
+        let mut line = line;
+        for _ in 0..n {
+            match self.prev_screen_line(line) {
+                Some(pos) => line = pos,
+                None => break,
+            }
+        }
+        line
+    }
+
+    fn scroll_wrapped(&mut self) {
+        let offset = self.screen_line_start(Pos::new(
+            cmp::min(self.offset.x, self.rows[self.offset.y].last_x()),
+            self.offset.y,
+        ));
+        if offset != self.offset {
+            self.offset = offset;
+            self.draw_range.full_expand();
+        }
+
+        let line = self.screen_line_start(self.cursor);
+        if line < self.offset {
+            self.offset = line;
+            self.draw_range.full_expand();
+        } else if self.screen_distance(self.offset, line, self.size.h) >= self.size.h {
+            self.offset = self.screen_lines_before(line, self.size.h.saturating_sub(1));
+            self.draw_range.full_expand();
+        }
+    }
+
+    fn scroll_center_wrapped(&mut self) {
+        let line = self.screen_line_start(self.cursor);
+        if line < self.offset || self.screen_distance(self.offset, line, self.size.h) >= self.size.h
+        {
+            self.offset = self.screen_lines_before(line, self.size.h / 2);
+            self.draw_range.full_expand();
+        }
+    }
+}
+
+impl Buffer {
+    fn read_region(&self, anchor: Pos) -> String {
         let pos1 = self.cursor.min(anchor);
         let pos2 = self.cursor.max(anchor);
+        self.rows.read_str(pos1, pos2)
+    }
 
-        for y in pos1.y..=pos2.y {
-            let row = &mut self.rows[y];
-            let x1 = if y == pos1.y { pos1.x } else { 0 };
-            let x2 = if y == pos2.y { pos2.x } else { row.last_x() };
+    // the rows between the cursor and pos change when either moves
+    fn redraw_region(&mut self, pos: Pos) {
+        let y1 = self.cursor.y.min(pos.y);
+        let y2 = self.cursor.y.max(pos.y);
+        self.draw_range.expand(y1, y2 + 1);
+    }
+
+    // the region and search matches drawn over the syntax faces of row y
+    fn overlays(&self, y: usize) -> Vec<Overlay> {
+        let mut overlays = Vec::new();
+
+        if self.cursor_line && y == self.cursor.y {
+            overlays.push(Overlay {
+                x_range: 0..usize::MAX,
+                fg: None,
+                bg: Bg::CurrentLine,
+            });
+        }
 
-            for i in row.x_to_idx(x1)..row.x_to_idx(x2) {
-                row.faces[i].1 = Bg::Default;
+        if let Some(anchor) = self.anchor {
+            let pos1 = self.cursor.min(anchor);
+            let pos2 = self.cursor.max(anchor);
+            if (pos1.y..=pos2.y).contains(&y) {
+                let x1 = if y == pos1.y { pos1.x } else { 0 };
+                let x2 = if y == pos2.y { pos2.x } else { usize::MAX };
+                overlays.push(Overlay {
+                    x_range: x1..x2,
+                    fg: None,
+                    bg: Bg::Region,
+                });
             }
-            if y < pos2.y {
-                row.trailing_bg = Bg::Default;
+        }
+
+        let matches = &self.search.matches;
+        let start = matches.partition_point(|mat| mat.pos.y < y);
+        for (i, mat) in matches.iter().enumerate().skip(start) {
+            if mat.pos.y > y {
+                break;
             }
+            let (fg, bg) = if i == self.search.match_idx {
+                (Fg::CurrentMatch, Bg::CurrentMatch)
+            } else {
+                (Fg::Match, Bg::Match)
+            };
+            overlays.push(Overlay {
+                x_range: mat.pos.x..mat.end_x,
+                fg: Some(fg),
+                bg,
+            });
         }
-        self.draw_range.expand(pos1.y, pos2.y + 1);
+
+        overlays
     }
 
-    fn indent_region(&mut self, anchor: Pos, unit: &str) {
+    fn indent_region(&mut self, anchor: Pos, unit: &str) -> Result<(), ReadOnly> {
         let pos1 = self.cursor.min(anchor);
         let pos2 = self.cursor.max(anchor);
         let eid = self.eid();
@@ -776,18 +1411,19 @@ impl Buffer {
             if indent_part == self.rows[y].string {
                 if !indent_part.is_empty() {
                     let event = Event::Indent(eid, Pos::new(0, y), "".into());
-                    let revent = self.process_event(event);
+                    let revent = self.process_event(event)?;
                     self.push_event(revent);
                 }
             } else if indent_part != string {
                 let event = Event::Indent(eid, Pos::new(0, y), string);
-                let revent = self.process_event(event);
+                let revent = self.process_event(event)?;
                 self.push_event(revent);
             }
         }
+        Ok(())
     }
 
-    fn remove_region(&mut self, anchor: Pos) {
+    fn remove_region(&mut self, anchor: Pos) -> Result<(), ReadOnly> {
         let pos1 = self.cursor.min(anchor);
         let pos2 = self.cursor.max(anchor);
         let event = if self.cursor < anchor {
@@ -795,21 +1431,20 @@ impl Buffer {
         } else {
             Event::RemoveMv(self.eid(), pos1, pos2)
         };
-        let revent = self.process_event(event);
+        let revent = self.process_event(event)?;
         self.push_event(revent);
         self.scroll();
-        self.rows[pos1.y].trailing_bg = Bg::Default;
+        Ok(())
     }
 }
 
 impl Buffer {
     pub fn search(&mut self, query: &str, backward: bool) {
-        for (y, row) in self.rows.iter_mut().enumerate() {
+        for (y, row) in self.rows.iter().enumerate() {
             for (idx, _) in row.string.match_indices(query) {
                 let pos = Pos::new(row.idx_to_x(idx), y);
-                let mut faces = vec![(Fg::Match, Bg::Match); query.len()];
-                faces.swap_with_slice(&mut row.faces[idx..(idx + query.len())]);
-                self.search.matches.push(Match { pos, faces });
+                let end_x = row.idx_to_x(idx + query.len());
+                self.search.matches.push(Match { pos, end_x });
             }
         }
 
@@ -830,7 +1465,6 @@ impl Buffer {
         self.search.orig_cursor = self.cursor;
 
         self.move_to_match();
-        self.highlight_match(true);
         self.draw_range.full_expand();
     }
 
@@ -840,8 +1474,6 @@ impl Buffer {
             return;
         }
 
-        self.highlight_match(false);
-
         self.search.match_idx = if backward {
             if self.search.match_idx > 0 {
                 self.search.match_idx - 1
@@ -857,7 +1489,6 @@ impl Buffer {
         };
 
         self.move_to_match();
-        self.highlight_match(true);
         self.draw_range.full_expand();
     }
 
@@ -866,11 +1497,6 @@ impl Buffer {
             return;
         }
 
-        for mat in self.search.matches.iter_mut() {
-            let row = &mut self.rows[mat.pos.y];
-            let idx = row.x_to_idx(mat.pos.x);
-            row.faces[idx..(idx + mat.faces.len())].swap_with_slice(&mut mat.faces);
-        }
         self.search.matches.clear();
 
         if restore {
@@ -878,9 +1504,7 @@ impl Buffer {
             self.cursor = self.search.orig_cursor;
         } else {
             self.saved_x = self.cursor.x;
-            if self.anchor.is_some() {
-                self.highlight_region(self.search.orig_cursor);
-            }
+            self.last_key = None;
         }
         self.draw_range.full_expand();
     }
@@ -890,18 +1514,250 @@ impl Buffer {
         self.cursor = mat.pos;
         self.scroll_center();
     }
+}
 
-    fn highlight_match(&mut self, current: bool) {
-        let mat = &self.search.matches[self.search.match_idx];
-        let row = &mut self.rows[mat.pos.y];
-        let idx = row.x_to_idx(mat.pos.x);
-        let face = if current {
-            (Fg::CurrentMatch, Bg::CurrentMatch)
-        } else {
-            (Fg::Match, Bg::Match)
+impl Buffer {
+    pub fn set_dired(&mut self, dired: Dired, select: Option<&str>) {
+        let y = select
+            .and_then(|name| dired.entries.iter().position(|e| e.name == name))
+            .map_or(1, |i| i + 1);
+
+        self.filename = Some(dired.path.display().to_string());
+        self.syntax = <dyn Syntax>::detect(None);
+        self.read_only = true;
+        self.rows = dired.lines().into_iter().map(Row::new).collect();
+        self.dired = Some(dired);
+        self.offset = Pos::new(0, 0);
+        self.cursor = Pos::new(0, cmp::min(y, self.rows.len() - 1));
+        self.saved_x = 0;
+        self.anchor = None;
+        self.last_key = None;
+        self.undo_list.clear();
+        self.redo_list.clear();
+        self.saved_eid = None;
+        self.search = Default::default();
+        self.syntax_update(0);
+        // not yet resized when listing a directory given on the command line
+        if self.size.h > 0 {
+            self.scroll_center();
+        }
+        self.draw_range.full_expand();
+    }
+
+    pub fn reload_dired(&mut self) -> io::Result<()> {
+        if let Some(dired) = self.dired.as_ref() {
+            let name = dired.entry(self.cursor.y).map(|e| e.name.clone());
+            let dired = Dired::read(&dired.path)?;
+            self.set_dired(dired, name.as_deref());
+        }
+        Ok(())
+    }
+
+    pub fn dired_entry(&self) -> Option<&Entry> {
+        self.dired.as_ref()?.entry(self.cursor.y)
+    }
+
+    pub fn dired_mark(&mut self, marked: bool) {
+        let y = self.cursor.y;
+        let line = match self.dired.as_mut().and_then(|d| d.entry_mut(y)) {
+            Some(entry) if entry.name != ".." => {
+                entry.marked = marked;
+                entry.to_line()
+            }
+            _ => return,
         };
-        for i in idx..(idx + mat.faces.len()) {
-            row.faces[i] = face;
+        self.rows[y] = Row::new(line);
+        self.syntax_update(y);
+
+        if y + 1 < self.rows.len() {
+            self.cursor = Pos::new(0, y + 1);
+            self.saved_x = 0;
+            self.scroll();
         }
     }
 }
+
+// writes a file through a temporary copy next to it, for contents read from
+// the file itself, then copies that over the file so that symlinks, hard
+// links, the owner and the permissions are kept; returns the path written
+fn write_in_place<F>(filename: &str, write: F) -> io::Result<PathBuf>
+where
+    F: FnOnce(&Path) -> io::Result<()>,
+{
+    let path = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".ele-tmp");
+    let tmp = PathBuf::from(tmp);
+
+    // opened without truncating, so that a failure here loses nothing
+    let file = write(&tmp).and_then(|_| {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+    });
+    let mut file = match file {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
+
+    // the file is cut short from here, so the copy is kept if this fails
+    let copied = file
+        .set_len(0)
+        .and_then(|_| io::copy(&mut File::open(&tmp)?, &mut file));
+    if let Err(e) = copied {
+        let message = format!("{}, the contents are in {}", e, tmp.display());
+        return Err(io::Error::new(e.kind(), message));
+    }
+
+    fs::remove_file(&tmp)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::{self as unix_fs, MetadataExt, PermissionsExt};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ele-buffer-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn write_in_place_keeps_links() {
+        let target = temp_path("target");
+        let link = temp_path("link");
+        let hard_link = temp_path("hard-link");
+        fs::write(&target, "old\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        let _ = fs::remove_file(&link);
+        let _ = fs::remove_file(&hard_link);
+        unix_fs::symlink(&target, &link).unwrap();
+        fs::hard_link(&target, &hard_link).unwrap();
+        let ino = fs::metadata(&target).unwrap().ino();
+
+        let path = write_in_place(link.to_str().unwrap(), |tmp| fs::write(tmp, "new\n")).unwrap();
+
+        assert_eq!(path, fs::canonicalize(&target).unwrap());
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&hard_link).unwrap(), "new\n");
+        let metadata = fs::metadata(&target).unwrap();
+        assert_eq!(metadata.ino(), ino);
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert!(!temp_path("target.ele-tmp").exists());
+
+        for path in &[&target, &link, &hard_link] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn write_in_place_removes_copy_on_failure() {
+        let target = temp_path("failed");
+        fs::write(&target, "old\n").unwrap();
+
+        let result = write_in_place(target.to_str().unwrap(), |tmp| {
+            fs::write(tmp, "partial")?;
+            Err(io::Error::other("failed"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old\n");
+        assert!(!temp_path("failed.ele-tmp").exists());
+        fs::remove_file(&target).unwrap();
+    }
+
+    fn set_ambiguous_width(buffer: &mut Buffer, width: usize) {
+        let history = buffer.unwind_history();
+        row::set_ambiguous_width(width);
+        buffer.update_mappings();
+        buffer.replay_history(history);
+    }
+
+    #[test]
+    fn undo_across_ambiguous_width_change() {
+        let mut buffer = Buffer::from_reader("test", "\u{25cb}\u{25cb}ab\n".as_bytes()).unwrap();
+        let mut clipboard = String::new();
+        let mut press = |buffer: &mut Buffer, key| {
+            buffer.process_key(key, &mut clipboard);
+        };
+        press(&mut buffer, Key::Ctrl(b'E'));
+        press(&mut buffer, Key::Char('x'));
+        press(&mut buffer, Key::Ctrl(b'A'));
+        press(&mut buffer, Key::Char('\u{25cb}'));
+
+        set_ambiguous_width(&mut buffer, 2);
+        let text = buffer.rows[0].string.clone();
+        let cursor = buffer.cursor;
+        press(&mut buffer, Key::Ctrl(b'_'));
+        press(&mut buffer, Key::Ctrl(b'_'));
+        let undone = buffer.rows[0].string.clone();
+
+        set_ambiguous_width(&mut buffer, 1);
+        press(&mut buffer, Key::Ctrl(b'F'));
+        press(&mut buffer, Key::Ctrl(b'_'));
+        let redone = buffer.rows[0].string.clone();
+
+        assert_eq!(text, "\u{25cb}\u{25cb}\u{25cb}abx");
+        assert_eq!((cursor.x, cursor.y), (2, 0));
+        assert_eq!(undone, "\u{25cb}\u{25cb}ab");
+        assert_eq!(redone, "\u{25cb}\u{25cb}abx");
+    }
+
+    #[test]
+    fn read_only_buffer_rejects_edits() {
+        let mut buffer = Buffer::from_reader("test", "abc\n".as_bytes()).unwrap();
+        let mut clipboard = String::new();
+        buffer.process_key(Key::Char('x'), &mut clipboard);
+        buffer.read_only = true;
+
+        for key in [Key::Char('y'), Key::Enter(Mod::NONE), Key::Ctrl(b'_')] {
+            assert_eq!(
+                buffer.process_key(key, &mut clipboard),
+                "Buffer is read-only"
+            );
+        }
+        assert_eq!(buffer.insert_char('z'), "Buffer is read-only");
+        assert_eq!(
+            buffer.process_key(Key::Ctrl(b'K'), &mut clipboard),
+            "Buffer is read-only"
+        );
+        assert_eq!(clipboard, "abc");
+        assert_eq!(buffer.rows[0].string, "xabc");
+
+        buffer.read_only = false;
+        buffer.process_key(Key::Char('y'), &mut clipboard);
+        buffer.process_key(Key::Ctrl(b'_'), &mut clipboard);
+        assert_eq!(buffer.rows[0].string, "xabc");
+        buffer.process_key(Key::Ctrl(b'_'), &mut clipboard);
+        assert_eq!(buffer.rows[0].string, "abc");
+    }
+
+    #[test]
+    fn unreadable_file_makes_buffer_read_only() {
+        let path = temp_path("unreadable");
+        let contents: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+        fs::write(&path, contents).unwrap();
+        let mut buffer = Buffer::new(None).unwrap();
+        buffer.rows = Rows::from_file(File::open(&path).unwrap()).unwrap();
+        assert!(buffer.check_unreadable().is_none());
+
+        fs::write(&path, "").unwrap();
+        let _ = &buffer.rows[50];
+        fs::remove_file(&path).unwrap();
+
+        let message = buffer.check_unreadable().unwrap();
+        assert!(message.starts_with("Cannot read file: "), "{}", message);
+        assert!(buffer.read_only);
+        assert!(buffer.check_unreadable().is_none());
+        assert_eq!(buffer.toggle_read_only(), "File could not be read");
+    }
+}
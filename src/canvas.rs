@@ -1,7 +1,8 @@
 use std::env;
 use std::io::{self, Write};
 
-use crate::face::{Bg, Fg};
+use crate::face::{Attr, Bg, Fg};
+use crate::input::{is_private_reply, Input, REPLY_TIMEOUT};
 
 #[derive(Clone, Copy)]
 pub enum Term {
@@ -22,13 +23,126 @@ impl Term {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Background {
+    Light,
+    Dark,
+}
+
+impl Background {
+    pub fn detect<W: Write>(input: &mut Input, output: &mut W) -> io::Result<Self> {
+        if let Some(background) = Self::query(input, output)? {
+            Ok(background)
+        } else if let Some(background) = Self::from_colorfgbg() {
+            Ok(background)
+        } else {
+            Ok(Self::Dark)
+        }
+    }
+
+    fn query<W: Write>(input: &mut Input, output: &mut W) -> io::Result<Option<Self>> {
+        // OSC 11: ask the terminal for its background color, then DA1,
+        // which every terminal answers, so that its reply ends the wait
+        output.write_all(b"\x1b]11;?\x1b\\\x1b[c")?;
+        output.flush()?;
+
+        let mut bytes = Vec::new();
+        let mut timeouts = 0;
+
+        while timeouts < 3 {
+            let b = match input.read_byte(REPLY_TIMEOUT)? {
+                Some(b) => b,
+                None => {
+                    timeouts += 1;
+                    continue;
+                }
+            };
+            bytes.push(b);
+
+            if b == b'c' {
+                if let Some(i) = bytes.iter().rposition(|&b| b == b'\x1b') {
+                    if is_private_reply(&bytes[i..]) {
+                        bytes.truncate(i);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let reply = take_osc_reply(&mut bytes);
+        // keep keys typed meanwhile for read_key
+        input.unread(&bytes);
+
+        let reply = match reply {
+            Some(reply) => String::from_utf8_lossy(&reply).into_owned(),
+            None => return Ok(None),
+        };
+        let rgb = match reply.find("rgb:") {
+            Some(i) => &reply[(i + 4)..],
+            None => return Ok(None),
+        };
+
+        let mut luma = 0.0;
+        let mut n = 0;
+
+        for (component, weight) in rgb.split('/').zip(&[0.299, 0.587, 0.114]) {
+            let max = match component.len() {
+                1..=4 => (1 << (4 * component.len())) - 1,
+                _ => return Ok(None),
+            };
+            match u32::from_str_radix(component, 16) {
+                Ok(value) => luma += weight * value as f64 / max as f64,
+                Err(_) => return Ok(None),
+            }
+            n += 1;
+        }
+
+        if n != 3 {
+            Ok(None)
+        } else if luma < 0.5 {
+            Ok(Some(Self::Dark))
+        } else {
+            Ok(Some(Self::Light))
+        }
+    }
+
+    fn from_colorfgbg() -> Option<Self> {
+        // e.g. "15;0" or "15;default;0" (the last field is the background)
+        let value = env::var("COLORFGBG").ok()?;
+        match value.rsplit(';').next()?.parse::<u8>().ok()? {
+            0..=6 | 8 => Some(Self::Dark),
+            _ => Some(Self::Light),
+        }
+    }
+}
+
+// removes "\x1b]11;{reply}" ended by BEL or ST from bytes
+fn take_osc_reply(bytes: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let start = bytes.windows(5).position(|w| w == b"\x1b]11;")?;
+    let (len, end) = bytes[start..]
+        .iter()
+        .enumerate()
+        .find_map(|(i, &b)| match b {
+            b'\x07' => Some((i, i + 1)),
+            b'\\' if bytes[start + i - 1] == b'\x1b' => Some((i - 1, i + 1)),
+            _ => None,
+        })?;
+    let reply = bytes[(start + 5)..(start + len)].to_vec();
+    bytes.drain(start..(start + end));
+    Some(reply)
+}
+
 pub struct Canvas {
     pub term: Term,
+    background: Background,
     bytes: Vec<u8>,
     current_fg: Option<Fg>,
     current_bg: Option<Bg>,
-    fg_colors: [Vec<u8>; 12],
-    bg_colors: [Vec<u8>; 5],
+    current_attr: Option<Attr>,
+    fg_colors: [Vec<u8>; 16],
+    bg_colors: [Vec<u8>; 6],
+    fg_attrs: [Attr; 16],
+    bg_attrs: [Attr; 6],
 }
 
 impl Write for Canvas {
@@ -44,22 +158,39 @@ impl Write for Canvas {
 }
 
 impl Canvas {
-    pub fn new() -> Self {
+    pub fn new(background: Background) -> Self {
         let mut canvas = Self {
             term: Term::detect(),
+            background,
             bytes: Vec::new(),
             current_fg: None,
             current_bg: None,
+            current_attr: None,
             fg_colors: Default::default(),
             bg_colors: Default::default(),
+            fg_attrs: Default::default(),
+            bg_attrs: Default::default(),
         };
         canvas.map_colors();
         canvas
     }
 
     fn map_colors(&mut self) {
-        // Tomorrow Night Bright
         // TODO: load config file
+        match self.background {
+            Background::Dark => self.map_dark_colors(),
+            Background::Light => self.map_light_colors(),
+        }
+        self.map_fg_attr(Fg::Keyword, Attr::BOLD);
+        self.map_fg_attr(Fg::Comment, Attr::ITALIC);
+        self.map_fg_attr(Fg::CurrentMatch, Attr::BOLD);
+        self.map_fg_attr(Fg::CurrentLineNumber, Attr::BOLD);
+        self.map_fg_attr(Fg::Error, Attr::UNDERLINE);
+        self.map_bg_attr(Bg::CurrentLine, Attr::REVERSE);
+    }
+
+    fn map_dark_colors(&mut self) {
+        // Tomorrow Night Bright
         match self.term {
             Term::TrueColor => {
                 self.map_fg_color(Fg::Default, fg_color!(234, 234, 234));
@@ -74,11 +205,16 @@ impl Canvas {
                 self.map_fg_color(Fg::Prompt, fg_color!(122, 166, 218));
                 self.map_fg_color(Fg::Match, fg_color!(0, 0, 0));
                 self.map_fg_color(Fg::CurrentMatch, fg_color!(0, 0, 0));
+                self.map_fg_color(Fg::LineNumber, fg_color!(96, 96, 96));
+                self.map_fg_color(Fg::CurrentLineNumber, fg_color!(234, 234, 234));
+                self.map_fg_color(Fg::Escape, fg_color!(213, 78, 83));
+                self.map_fg_color(Fg::Error, fg_color!(213, 78, 83));
                 self.map_bg_color(Bg::Default, bg_color!(0, 0, 0));
                 self.map_bg_color(Bg::Region, bg_color!(66, 66, 66));
                 self.map_bg_color(Bg::StatusBar, bg_color!(28, 28, 28));
                 self.map_bg_color(Bg::Match, bg_color!(231, 197, 71));
                 self.map_bg_color(Bg::CurrentMatch, bg_color!(231, 140, 69));
+                self.map_bg_color(Bg::CurrentLine, bg_color!(0, 0, 0));
             }
             Term::Color256 => {
                 self.map_fg_color(Fg::Default, fg_color256!(255));
@@ -93,11 +229,16 @@ impl Canvas {
                 self.map_fg_color(Fg::Prompt, fg_color256!(110));
                 self.map_fg_color(Fg::Match, fg_color256!(16));
                 self.map_fg_color(Fg::CurrentMatch, fg_color256!(16));
+                self.map_fg_color(Fg::LineNumber, fg_color256!(241));
+                self.map_fg_color(Fg::CurrentLineNumber, fg_color256!(255));
+                self.map_fg_color(Fg::Escape, fg_color256!(167));
+                self.map_fg_color(Fg::Error, fg_color256!(167));
                 self.map_bg_color(Bg::Default, bg_color256!(16));
                 self.map_bg_color(Bg::Region, bg_color256!(238));
                 self.map_bg_color(Bg::StatusBar, bg_color256!(234));
                 self.map_bg_color(Bg::Match, bg_color256!(179));
                 self.map_bg_color(Bg::CurrentMatch, bg_color256!(173));
+                self.map_bg_color(Bg::CurrentLine, bg_color256!(16));
             }
             Term::Color16 => {
                 self.map_fg_color(Fg::Default, fg_color16!(white));
@@ -112,11 +253,94 @@ impl Canvas {
                 self.map_fg_color(Fg::Prompt, fg_color16!(blue));
                 self.map_fg_color(Fg::Match, fg_color16!(black));
                 self.map_fg_color(Fg::CurrentMatch, fg_color16!(black));
+                self.map_fg_color(Fg::LineNumber, fg_color16!(bright_black));
+                self.map_fg_color(Fg::CurrentLineNumber, fg_color16!(white));
+                self.map_fg_color(Fg::Escape, fg_color16!(red));
+                self.map_fg_color(Fg::Error, fg_color16!(red));
                 self.map_bg_color(Bg::Default, bg_color16!(black));
                 self.map_bg_color(Bg::Region, bg_color16!(bright_black));
                 self.map_bg_color(Bg::StatusBar, bg_color16!(bright_black));
                 self.map_bg_color(Bg::Match, bg_color16!(yellow));
                 self.map_bg_color(Bg::CurrentMatch, bg_color16!(red));
+                self.map_bg_color(Bg::CurrentLine, bg_color16!(black));
+            }
+        }
+    }
+
+    fn map_light_colors(&mut self) {
+        // Tomorrow
+        match self.term {
+            Term::TrueColor => {
+                self.map_fg_color(Fg::Default, fg_color!(77, 77, 76));
+                self.map_fg_color(Fg::Keyword, fg_color!(137, 89, 168));
+                self.map_fg_color(Fg::Type, fg_color!(200, 150, 0));
+                self.map_fg_color(Fg::Module, fg_color!(62, 153, 159));
+                self.map_fg_color(Fg::Variable, fg_color!(245, 135, 31));
+                self.map_fg_color(Fg::Function, fg_color!(66, 113, 174));
+                self.map_fg_color(Fg::Macro, fg_color!(62, 153, 159));
+                self.map_fg_color(Fg::String, fg_color!(113, 140, 0));
+                self.map_fg_color(Fg::Comment, fg_color!(142, 144, 140));
+                self.map_fg_color(Fg::Prompt, fg_color!(66, 113, 174));
+                self.map_fg_color(Fg::Match, fg_color!(0, 0, 0));
+                self.map_fg_color(Fg::CurrentMatch, fg_color!(0, 0, 0));
+                self.map_fg_color(Fg::LineNumber, fg_color!(180, 180, 180));
+                self.map_fg_color(Fg::CurrentLineNumber, fg_color!(77, 77, 76));
+                self.map_fg_color(Fg::Escape, fg_color!(200, 40, 41));
+                self.map_fg_color(Fg::Error, fg_color!(200, 40, 41));
+                self.map_bg_color(Bg::Default, bg_color!(255, 255, 255));
+                self.map_bg_color(Bg::Region, bg_color!(214, 214, 214));
+                self.map_bg_color(Bg::StatusBar, bg_color!(239, 239, 239));
+                self.map_bg_color(Bg::Match, bg_color!(234, 183, 0));
+                self.map_bg_color(Bg::CurrentMatch, bg_color!(245, 135, 31));
+                self.map_bg_color(Bg::CurrentLine, bg_color!(255, 255, 255));
+            }
+            Term::Color256 => {
+                self.map_fg_color(Fg::Default, fg_color256!(238));
+                self.map_fg_color(Fg::Keyword, fg_color256!(97));
+                self.map_fg_color(Fg::Type, fg_color256!(178));
+                self.map_fg_color(Fg::Module, fg_color256!(73));
+                self.map_fg_color(Fg::Variable, fg_color256!(208));
+                self.map_fg_color(Fg::Function, fg_color256!(67));
+                self.map_fg_color(Fg::Macro, fg_color256!(73));
+                self.map_fg_color(Fg::String, fg_color256!(64));
+                self.map_fg_color(Fg::Comment, fg_color256!(245));
+                self.map_fg_color(Fg::Prompt, fg_color256!(67));
+                self.map_fg_color(Fg::Match, fg_color256!(16));
+                self.map_fg_color(Fg::CurrentMatch, fg_color256!(16));
+                self.map_fg_color(Fg::LineNumber, fg_color256!(249));
+                self.map_fg_color(Fg::CurrentLineNumber, fg_color256!(238));
+                self.map_fg_color(Fg::Escape, fg_color256!(160));
+                self.map_fg_color(Fg::Error, fg_color256!(160));
+                self.map_bg_color(Bg::Default, bg_color256!(231));
+                self.map_bg_color(Bg::Region, bg_color256!(252));
+                self.map_bg_color(Bg::StatusBar, bg_color256!(255));
+                self.map_bg_color(Bg::Match, bg_color256!(178));
+                self.map_bg_color(Bg::CurrentMatch, bg_color256!(208));
+                self.map_bg_color(Bg::CurrentLine, bg_color256!(231));
+            }
+            Term::Color16 => {
+                self.map_fg_color(Fg::Default, fg_color16!(black));
+                self.map_fg_color(Fg::Keyword, fg_color16!(magenta));
+                self.map_fg_color(Fg::Type, fg_color16!(yellow));
+                self.map_fg_color(Fg::Module, fg_color16!(cyan));
+                self.map_fg_color(Fg::Variable, fg_color16!(red));
+                self.map_fg_color(Fg::Function, fg_color16!(blue));
+                self.map_fg_color(Fg::Macro, fg_color16!(cyan));
+                self.map_fg_color(Fg::String, fg_color16!(green));
+                self.map_fg_color(Fg::Comment, fg_color16!(bright_black));
+                self.map_fg_color(Fg::Prompt, fg_color16!(blue));
+                self.map_fg_color(Fg::Match, fg_color16!(black));
+                self.map_fg_color(Fg::CurrentMatch, fg_color16!(black));
+                self.map_fg_color(Fg::LineNumber, fg_color16!(bright_black));
+                self.map_fg_color(Fg::CurrentLineNumber, fg_color16!(black));
+                self.map_fg_color(Fg::Escape, fg_color16!(red));
+                self.map_fg_color(Fg::Error, fg_color16!(red));
+                self.map_bg_color(Bg::Default, bg_color16!(bright_white));
+                self.map_bg_color(Bg::Region, bg_color16!(white));
+                self.map_bg_color(Bg::StatusBar, bg_color16!(white));
+                self.map_bg_color(Bg::Match, bg_color16!(yellow));
+                self.map_bg_color(Bg::CurrentMatch, bg_color16!(red));
+                self.map_bg_color(Bg::CurrentLine, bg_color16!(bright_white));
             }
         }
     }
@@ -129,6 +353,14 @@ impl Canvas {
         self.bg_colors[bg as usize].extend_from_slice(color);
     }
 
+    fn map_fg_attr(&mut self, fg: Fg, attr: Attr) {
+        self.fg_attrs[fg as usize] = attr;
+    }
+
+    fn map_bg_attr(&mut self, bg: Bg, attr: Attr) {
+        self.bg_attrs[bg as usize] = attr;
+    }
+
     #[inline]
     pub fn set_cursor(&mut self, x: usize, y: usize) -> io::Result<()> {
         write!(self.bytes, "\x1b[{};{}H", y + 1, x + 1)
@@ -140,7 +372,7 @@ impl Canvas {
             self.bytes.write(&self.fg_colors[fg as usize])?;
             self.current_fg = Some(fg);
         }
-        Ok(())
+        self.update_attr()
     }
 
     #[inline]
@@ -149,6 +381,40 @@ impl Canvas {
             self.bytes.write(&self.bg_colors[bg as usize])?;
             self.current_bg = Some(bg);
         }
+        self.update_attr()
+    }
+
+    fn update_attr(&mut self) -> io::Result<()> {
+        let fg_attr = self
+            .current_fg
+            .map_or(Attr::NONE, |fg| self.fg_attrs[fg as usize]);
+        let bg_attr = self
+            .current_bg
+            .map_or(Attr::NONE, |bg| self.bg_attrs[bg as usize]);
+        let attr = fg_attr | bg_attr;
+
+        if self.current_attr == Some(attr) {
+            return Ok(());
+        }
+
+        let mut params = Vec::new();
+
+        for &(flag, on, off) in &[
+            (Attr::BOLD, "1", "22"),
+            (Attr::ITALIC, "3", "23"),
+            (Attr::UNDERLINE, "4", "24"),
+            (Attr::REVERSE, "7", "27"),
+        ] {
+            let was_set = self.current_attr.map(|a| a.contains(flag));
+            match (was_set, attr.contains(flag)) {
+                (Some(true), true) | (Some(false), false) => (),
+                (_, true) => params.push(on),
+                (_, false) => params.push(off),
+            }
+        }
+
+        write!(self.bytes, "\x1b[{}m", params.join(";"))?;
+        self.current_attr = Some(attr);
         Ok(())
     }
 
@@ -157,6 +423,7 @@ impl Canvas {
         self.bytes.write(b"\x1b[m")?;
         self.current_fg = None;
         self.current_bg = None;
+        self.current_attr = Some(Attr::NONE);
         Ok(())
     }
 
@@ -173,6 +440,7 @@ impl Canvas {
         self.bytes.clear();
         self.current_fg = None;
         self.current_bg = None;
+        self.current_attr = None;
     }
 
     #[inline]
@@ -180,3 +448,23 @@ impl Canvas {
         &self.bytes[..]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osc_reply_is_taken_from_typed_keys() {
+        let mut bytes = b"ab\x1b]11;rgb:ffff/ffff/ffff\x1b\\cd".to_vec();
+        assert_eq!(take_osc_reply(&mut bytes).unwrap(), b"rgb:ffff/ffff/ffff");
+        assert_eq!(bytes, b"abcd");
+
+        let mut bytes = b"\x1b]11;rgb:00/00/00\x07x".to_vec();
+        assert_eq!(take_osc_reply(&mut bytes).unwrap(), b"rgb:00/00/00");
+        assert_eq!(bytes, b"x");
+
+        let mut bytes = b"\\x".to_vec();
+        assert_eq!(take_osc_reply(&mut bytes), None);
+        assert_eq!(bytes, b"\\x");
+    }
+}
@@ -0,0 +1,74 @@
+extern crate unicode_names2;
+
+use std::sync::OnceLock;
+
+// all Unicode names in alphabetical order, listed on first completion
+static NAMES: OnceLock<Vec<String>> = OnceLock::new();
+
+// a Unicode name in any case, or a hex codepoint such as "U+03BB" or "3bb";
+// without "U+", names come first, so that "bed" is U+1F6CF BED
+pub fn parse(input: &str) -> Option<char> {
+    let input = input.trim();
+    let hex = match input
+        .strip_prefix("U+")
+        .or_else(|| input.strip_prefix("u+"))
+    {
+        Some(hex) => hex,
+        None => match unicode_names2::character(input) {
+            Some(ch) => return Some(ch),
+            None => input,
+        },
+    };
+    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+}
+
+// Unicode names starting with the input, in alphabetical order
+pub fn complete(input: &str) -> Vec<String> {
+    let names = NAMES.get_or_init(|| {
+        let mut names: Vec<_> = (0..=char::MAX as u32)
+            .filter_map(char::from_u32)
+            .filter_map(unicode_names2::name)
+            .map(|name| name.to_string())
+            .collect();
+        names.sort();
+        names
+    });
+
+    let prefix = input.to_uppercase();
+    let start = names.partition_point(|name| name.as_str() < prefix.as_str());
+    let len = names[start..].partition_point(|name| name.starts_with(&prefix));
+    names[start..(start + len)].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_prefix() {
+        assert_eq!(
+            complete("greek small letter lam"),
+            ["GREEK SMALL LETTER LAMDA"]
+        );
+        assert_eq!(complete("snowman"), ["SNOWMAN", "SNOWMAN WITHOUT SNOW"]);
+        assert!(complete("NO SUCH CHARACTER").is_empty());
+
+        let names = complete("LATIN SMALL LETTER A");
+        assert!(names.len() > 1);
+        assert!(names.windows(2).all(|w| w[0] < w[1]));
+        assert!(names
+            .iter()
+            .all(|name| name.starts_with("LATIN SMALL LETTER A")));
+    }
+
+    #[test]
+    fn parse_names_and_codepoints() {
+        assert_eq!(parse("U+03BB"), Some('λ'));
+        assert_eq!(parse("3bb"), Some('λ'));
+        assert_eq!(parse("bed"), Some('\u{1f6cf}'));
+        assert_eq!(parse("U+bed"), Some('\u{bed}'));
+        assert_eq!(parse("u+snowman"), None);
+        assert_eq!(parse("snowman"), Some('☃'));
+        assert_eq!(parse("not a name"), None);
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+pub struct Dired {
+    pub path: PathBuf,
+    pub entries: Vec<Entry>,
+}
+
+pub struct Entry {
+    pub name: String,
+    // a symlink is neither, even if it points to a directory
+    pub is_dir: bool,
+    link: Option<PathBuf>,
+    pub marked: bool,
+    size: u64,
+    mtime: Option<i64>,
+}
+
+// the listing starts with a header row
+const HEADER_LEN: usize = 1;
+
+impl Dired {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let path = path.canonicalize()?;
+        let mut entries = Vec::new();
+
+        for dir_entry in fs::read_dir(&path)? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name().to_string_lossy().into_owned();
+            entries.push(Entry::new(name, &dir_entry.path()));
+        }
+
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+        if let Some(parent) = path.parent() {
+            entries.insert(0, Entry::new("..".into(), parent));
+        }
+
+        Ok(Self { path, entries })
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("  {}:", self.path.display())];
+        lines.extend(self.entries.iter().map(|entry| entry.to_line()));
+        lines
+    }
+
+    pub fn entry(&self, y: usize) -> Option<&Entry> {
+        self.entries.get(y.checked_sub(HEADER_LEN)?)
+    }
+
+    pub fn entry_mut(&mut self, y: usize) -> Option<&mut Entry> {
+        self.entries.get_mut(y.checked_sub(HEADER_LEN)?)
+    }
+
+    pub fn marked(&self) -> Vec<&Entry> {
+        self.entries.iter().filter(|entry| entry.marked).collect()
+    }
+
+    pub fn delete_marked(&self) -> io::Result<usize> {
+        let mut n = 0;
+        for entry in self.marked() {
+            let path = self.path.join(&entry.name);
+            // removes a symlink itself, not what it points to
+            if entry.is_dir {
+                fs::remove_dir(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl Entry {
+    fn new(name: String, path: &Path) -> Self {
+        let metadata = fs::symlink_metadata(path).ok();
+        Self {
+            name,
+            is_dir: matches!(&metadata, Some(m) if m.is_dir()),
+            link: fs::read_link(path).ok(),
+            marked: false,
+            size: metadata.as_ref().map_or(0, |m| m.len()),
+            mtime: metadata
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64),
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "{} {:>10} {} {}{}",
+            if self.marked { 'D' } else { ' ' },
+            self.size,
+            self.mtime.map_or("----------------".into(), format_time),
+            self.name,
+            match &self.link {
+                Some(target) => format!(" -> {}", target.display()),
+                None if self.is_dir => "/".into(),
+                None => "".into(),
+            },
+        )
+    }
+}
+
+fn format_time(secs: i64) -> String {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs as unix_fs;
+    use std::process;
+
+    #[test]
+    fn delete_symlink_to_dir() {
+        let path = env::temp_dir().join(format!("ele-dired-{}", process::id()));
+        let target = path.join("target");
+        fs::create_dir_all(target.join("kept")).unwrap();
+        unix_fs::symlink(&target, path.join("link")).unwrap();
+
+        let mut dired = Dired::read(&path).unwrap();
+        let link = dired.entries.iter_mut().find(|e| e.name == "link").unwrap();
+        assert!(!link.is_dir);
+        assert!(link
+            .to_line()
+            .ends_with(&format!("link -> {}", target.display())));
+        link.marked = true;
+
+        let result = dired.delete_marked();
+        let link_exists = fs::symlink_metadata(path.join("link")).is_ok();
+        let kept = target.join("kept").is_dir();
+        fs::remove_dir_all(&path).unwrap();
+        assert_eq!(result.unwrap(), 1);
+        assert!(!link_exists);
+        assert!(kept);
+    }
+}
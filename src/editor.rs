@@ -1,68 +1,161 @@
-use signal_hook::{self, consts::signal::SIGWINCH};
-use std::io::{self, Read, Write};
+use signal_hook::{
+    self,
+    consts::signal::{SIGHUP, SIGSTOP, SIGTERM, SIGWINCH},
+};
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
 use std::str;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use crate::args::{parse_line_col, Args};
 use crate::buffer::Buffer;
-use crate::canvas::Canvas;
+use crate::canvas::{Background, Canvas};
+use crate::charname;
 use crate::coord::{Pos, Size};
-use crate::key::{Key, KeyError};
+use crate::dired::Dired;
+use crate::input::{is_private_reply, Input, REPLY_TIMEOUT};
+use crate::key::{Key, KeyError, Mod};
 use crate::minibuffer::Minibuffer;
+use crate::raw_mode::RawMode;
+use crate::row;
+
+const LARGE_FILE_WARNING: &str =
+    "Large file: lines are read on demand and highlighted only near the screen";
 
 #[derive(PartialEq)]
 enum State {
     Default,
+    Meta,
+    // octal digits typed so far after C-q
+    QuotedInsert { code: u32, digits: u32 },
     Search { backward: bool },
     CtrlX,
+    CtrlX8,
+    AltG,
+    GotoLine,
+    GotoChar,
+    InsertChar,
     Save,
+    DiredDelete,
     Quit,
     Quitted,
 }
 
 pub struct Editor {
-    stdin: io::Stdin,
+    raw_mode: RawMode,
+    input: Input,
     stdout: io::Stdout,
     canvas: Canvas,
     state: State,
-    buffer: Buffer,
+    buffers: Vec<Buffer>,
+    current: usize,
+    size: Size,
     minibuffer: Minibuffer,
     clipboard: String,
     screen_resized: Arc<AtomicBool>,
+    terminated: Arc<AtomicBool>,
 }
 
 impl Editor {
-    pub fn new(filename: Option<String>) -> io::Result<Self> {
+    pub fn new(args: Args, stdin: &[u8], tty: File, raw_mode: RawMode) -> io::Result<Self> {
+        let mut input = Input::new(tty);
+        let mut stdout = io::stdout();
+        let background = Background::detect(&mut input, &mut stdout)?;
+
+        let mut buffers = Vec::new();
+        for file in args.files.iter() {
+            let mut buffer = match file.filename.as_deref() {
+                Some(filename) => Buffer::new(Some(filename.to_string()))?,
+                None => Buffer::from_reader("*stdin*", stdin)?,
+            };
+            buffer.read_only |= args.read_only;
+            buffers.push(buffer);
+        }
+        if buffers.is_empty() {
+            let mut buffer = Buffer::new(None)?;
+            buffer.read_only |= args.read_only;
+            buffers.push(buffer);
+        }
+
         let mut editor = Self {
-            stdin: io::stdin(),
-            stdout: io::stdout(),
-            canvas: Canvas::new(),
+            raw_mode,
+            input,
+            stdout,
+            canvas: Canvas::new(background),
             state: State::Default,
-            buffer: Buffer::new(filename)?,
+            buffers,
+            current: 0,
+            size: Size::new(0, 0),
             minibuffer: Minibuffer::new(),
             clipboard: String::new(),
-            screen_resized: Arc::new(AtomicBool::new(true)),
+            screen_resized: Arc::new(AtomicBool::new(false)),
+            terminated: Arc::new(AtomicBool::new(false)),
         };
 
         // switch to alternate screen buffer
         editor.stdout.write(b"\x1b[?1049h")?;
         editor.stdout.flush()?;
 
+        // the alternate screen has its own keyboard mode stack
+        editor.input.kitty_keyboard = editor.detect_kitty_keyboard()?;
+        editor.push_keyboard_mode()?;
+
+        // the width of East Asian ambiguous characters such as U+25CB
+        let ambiguous_width = match env::var("ELE_AMBIGUOUS_WIDTH").as_deref() {
+            Ok("2") => Some(2),
+            Ok("auto") => editor.detect_ambiguous_width()?,
+            _ => None,
+        };
+        if let Some(width) = ambiguous_width {
+            editor.set_ambiguous_width(width);
+        }
+
         // detect screen resizing
         signal_hook::flag::register(SIGWINCH, Arc::clone(&editor.screen_resized))?;
 
+        // save modified buffers before exiting on hangup or termination
+        signal_hook::flag::register(SIGHUP, Arc::clone(&editor.terminated))?;
+        signal_hook::flag::register(SIGTERM, Arc::clone(&editor.terminated))?;
+
+        editor.resize()?;
+
+        for (buffer, file) in editor.buffers.iter_mut().zip(args.files.iter()) {
+            if let Some(line) = file.line {
+                buffer.goto_line(line, file.col.unwrap_or(1));
+            }
+        }
+        if editor.buffers[editor.current].is_lazy() {
+            editor.minibuffer.set_message(LARGE_FILE_WARNING);
+        }
+
         Ok(editor)
     }
 
     pub fn run(&mut self) -> io::Result<()> {
-        while self.state != State::Quitted {
+        let result = self.event_loop();
+        if self.terminated.load(Ordering::Relaxed) {
+            // the terminal may already be gone, so ignore errors from it
+            return self.emergency_save();
+        }
+        result
+    }
+
+    fn event_loop(&mut self) -> io::Result<()> {
+        while self.state != State::Quitted && !self.terminated.load(Ordering::Relaxed) {
             if self.screen_resized.swap(false, Ordering::Relaxed) {
                 self.resize()?;
             }
 
             self.draw()?;
 
-            match self.read_key() {
+            let (resized, terminated) = (&self.screen_resized, &self.terminated);
+            let interrupted =
+                || resized.load(Ordering::Relaxed) || terminated.load(Ordering::Relaxed);
+
+            match self.input.read_key(interrupted) {
                 Ok(key) => self.process_key(key)?,
                 Err(KeyError::IoError(e)) => return Err(e),
                 _ => (),
@@ -72,116 +165,200 @@ impl Editor {
     }
 
     fn resize(&mut self) -> io::Result<()> {
-        self.stdout.write(b"\x1b[999C\x1b[999B")?;
-        self.stdout.write(b"\x1b[6n")?;
+        let (w, h) = match self.window_size() {
+            Some(size) => size,
+            None => self.query_window_size()?,
+        };
+
+        self.size = Size::new(w, h - 2);
+        for buffer in self.buffers.iter_mut() {
+            buffer.resize(Pos::new(0, 0), self.size);
+        }
+        self.minibuffer.resize(Pos::new(0, h - 1), Size::new(w, 1));
+        Ok(())
+    }
+
+    // asks for the kitty keyboard flags, followed by the primary device
+    // attributes that every terminal answers
+    fn detect_kitty_keyboard(&mut self) -> io::Result<bool> {
+        self.stdout.write(b"\x1b[?u\x1b[c")?;
         self.stdout.flush()?;
 
-        let mut buf = [0];
-        let mut num = 0;
-        let (mut w, mut h) = (0, 0);
+        let mut input = Vec::new();
+        let mut supported = false;
+        let mut timeouts = 0;
 
-        while self.stdin.read(&mut buf)? == 1 {
-            match buf[0] {
-                b'\x1b' | b'[' => (),
-                b';' => {
-                    h = num;
-                    num = 0;
+        while timeouts < 3 {
+            let b = match self.input.read_byte(REPLY_TIMEOUT)? {
+                Some(b) => b,
+                None => {
+                    timeouts += 1;
+                    continue;
                 }
-                b'R' => {
-                    w = num;
-                    break;
-                }
-                ch => {
-                    num = num * 10 + (ch - b'0') as usize;
+            };
+            input.push(b);
+
+            if let b'u' | b'c' = b {
+                if let Some(i) = input.iter().rposition(|&b| b == b'\x1b') {
+                    if is_private_reply(&input[i..]) {
+                        input.truncate(i);
+                        if b == b'c' {
+                            break;
+                        }
+                        supported = true;
+                    }
                 }
             }
         }
 
-        self.buffer.resize(Pos::new(0, 0), Size::new(w, h - 2));
-        self.minibuffer.resize(Pos::new(0, h - 1), Size::new(w, 1));
-        Ok(())
+        // keep keys typed meanwhile for read_key
+        self.input.unread(&input);
+        Ok(supported)
     }
 
-    fn draw(&mut self) -> io::Result<()> {
-        self.canvas.write(b"\x1b[?25l")?;
+    // disambiguate escape codes, so that e.g. C-i and TAB differ, and
+    // report shifted keys, so that C-S-- is known to be C-_
+    fn push_keyboard_mode(&mut self) -> io::Result<()> {
+        if self.input.kitty_keyboard {
+            self.stdout.write(b"\x1b[>5u")?;
+        }
+        self.stdout.flush()
+    }
 
-        self.buffer.draw(&mut self.canvas)?;
-        self.minibuffer.draw(&mut self.canvas)?;
+    fn pop_keyboard_mode(&mut self) -> io::Result<()> {
+        if self.input.kitty_keyboard {
+            self.stdout.write(b"\x1b[<u")?;
+        }
+        self.stdout.flush()
+    }
 
-        match self.state {
-            State::Default | State::CtrlX => {
-                self.buffer.draw_cursor(&mut self.canvas)?;
-            }
-            State::Search { .. } | State::Save | State::Quit => {
-                self.minibuffer.draw_cursor(&mut self.canvas)?;
-            }
-            State::Quitted => unreachable!(),
+    fn window_size(&self) -> Option<(usize, usize)> {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(self.input.as_raw_fd(), libc::TIOCGWINSZ, &mut ws) };
+        if result == 0 && ws.ws_col > 0 && ws.ws_row > 0 {
+            Some((ws.ws_col as usize, ws.ws_row as usize))
+        } else {
+            None
         }
+    }
 
-        self.canvas.write(b"\x1b[?25h")?;
+    // fallback for terminals without TIOCGWINSZ: move the cursor to the
+    // bottom right corner and ask for its position
+    fn query_window_size(&mut self) -> io::Result<(usize, usize)> {
+        self.stdout.write(b"\x1b[999C\x1b[999B")?;
+        self.stdout.write(b"\x1b[6n")?;
+        self.stdout.flush()?;
 
-        self.stdout.write(self.canvas.as_bytes())?;
-        self.canvas.clear();
-        self.stdout.flush()
+        match self.read_cursor_report()? {
+            Some((h, w)) => Ok((w, h)),
+            None => Ok((80, 24)),
+        }
     }
 
-    fn read_key(&mut self) -> Result<Key, KeyError> {
-        let mut buf = [0];
+    // prints a probe character and checks how far the cursor moved
+    fn detect_ambiguous_width(&mut self) -> io::Result<Option<usize>> {
+        self.stdout.write("\r\u{25cb}\x1b[6n".as_bytes())?;
+        self.stdout.flush()?;
+        let report = self.read_cursor_report()?;
+        self.stdout.write(b"\r\x1b[K")?;
+        self.stdout.flush()?;
+
+        match report {
+            Some((_, col @ 2..=3)) => Ok(Some(col - 1)),
+            _ => Ok(None),
+        }
+    }
 
-        while self.stdin.read(&mut buf)? == 0 {
-            if self.screen_resized.load(Ordering::Relaxed) {
-                return Err(KeyError::Interrupted);
+    fn read_cursor_report(&mut self) -> io::Result<Option<(usize, usize)>> {
+        let mut input = Vec::new();
+        let mut timeouts = 0;
+
+        while timeouts < 10 {
+            let b = match self.input.read_byte(REPLY_TIMEOUT)? {
+                Some(b) => b,
+                None => {
+                    timeouts += 1;
+                    continue;
+                }
+            };
+            input.push(b);
+
+            if b == b'R' {
+                if let Some(i) = input.iter().rposition(|&b| b == b'\x1b') {
+                    if let Some(report) = parse_cursor_report(&input[i..]) {
+                        // keep keys typed meanwhile for read_key
+                        self.input.unread(&input[..i]);
+                        return Ok(Some(report));
+                    }
+                }
             }
         }
 
-        match buf[0] {
-            0..=26 | 28..=31 => Ok(Key::Ctrl(b'@' + buf[0])),
-            27 => match self.read_escape_sequence()? {
-                [0, 0, 0] => Ok(Key::Escape),
-                [b, 0, 0] => Ok(Key::Alt(b)),
-                [b'[', b'A', 0] => Ok(Key::ArrowUp),
-                [b'[', b'B', 0] => Ok(Key::ArrowDown),
-                [b'[', b'C', 0] => Ok(Key::ArrowRight),
-                [b'[', b'D', 0] => Ok(Key::ArrowLeft),
-                [b'[', b'F', 0] => Ok(Key::End),
-                [b'[', b'H', 0] => Ok(Key::Home),
-                [b'[', b'O', b'F'] => Ok(Key::End),
-                [b'[', b'O', b'H'] => Ok(Key::Home),
-                [b'[', b'1', b'~'] => Ok(Key::Home),
-                [b'[', b'3', b'~'] => Ok(Key::Delete),
-                [b'[', b'4', b'~'] => Ok(Key::End),
-                [b'[', b'5', b'~'] => Ok(Key::PageUp),
-                [b'[', b'6', b'~'] => Ok(Key::PageDown),
-                [b'[', b'7', b'~'] => Ok(Key::Home),
-                [b'[', b'8', b'~'] => Ok(Key::End),
-                _ => Err(KeyError::UnknownKey),
-            },
-            32..=126 => Ok(Key::Char(buf[0] as char)),
-            127 => Ok(Key::Backspace),
-            _ => match self.read_utf8(buf[0])? {
-                Some(ch) => Ok(Key::Char(ch)),
-                None => Err(KeyError::UnknownKey),
-            },
+        self.input.unread(&input);
+        Ok(None)
+    }
+
+    // undo history holds columns, so it is replayed with the new widths
+    fn set_ambiguous_width(&mut self, width: usize) {
+        let histories: Vec<_> = self
+            .buffers
+            .iter_mut()
+            .map(|buffer| buffer.unwind_history())
+            .collect();
+        row::set_ambiguous_width(width);
+        for (buffer, history) in self.buffers.iter_mut().zip(histories) {
+            buffer.update_mappings();
+            buffer.replay_history(history);
         }
+        self.minibuffer.update_mappings();
     }
 
-    fn read_escape_sequence(&mut self) -> io::Result<[u8; 3]> {
-        let mut buf = [0; 3];
-        self.stdin.read(&mut buf)?; // can result in a timeout
-        Ok(buf)
+    fn toggle_ambiguous_width(&mut self) {
+        let width = if row::ambiguous_width() == 2 { 1 } else { 2 };
+        self.set_ambiguous_width(width);
+        self.minibuffer
+            .set_message(&format!("Ambiguous width set to {}", width));
+        self.state = State::Default;
     }
 
-    fn read_utf8(&mut self, first_byte: u8) -> io::Result<Option<char>> {
-        let mut buf = [first_byte, 0, 0, 0];
+    fn draw(&mut self) -> io::Result<()> {
+        self.canvas.write(b"\x1b[?25l")?;
 
-        for i in 1..buf.len() {
-            self.stdin.read(&mut buf[i..=i])?;
+        self.buffers[self.current].draw(&mut self.canvas)?;
+        // rows read for drawing may fail to read, but prompts stay
+        if let State::Default = self.state {
+            if let Some(message) = self.buffers[self.current].check_unreadable() {
+                self.minibuffer.set_message(&message);
+            }
+        }
+        self.minibuffer.draw(&mut self.canvas)?;
 
-            if let Ok(s) = str::from_utf8(&buf[0..=i]) {
-                return Ok(s.chars().next());
+        match self.state {
+            State::Default
+            | State::Meta
+            | State::QuotedInsert { .. }
+            | State::CtrlX
+            | State::CtrlX8
+            | State::AltG => {
+                self.buffers[self.current].draw_cursor(&mut self.canvas)?;
+            }
+            State::Search { .. }
+            | State::GotoLine
+            | State::GotoChar
+            | State::InsertChar
+            | State::Save
+            | State::DiredDelete
+            | State::Quit => {
+                self.minibuffer.draw_cursor(&mut self.canvas)?;
             }
+            State::Quitted => unreachable!(),
         }
-        Ok(None)
+
+        self.canvas.write(b"\x1b[?25h")?;
+
+        self.stdout.write(self.canvas.as_bytes())?;
+        self.canvas.clear();
+        self.stdout.flush()
     }
 
     fn process_key(&mut self, key: Key) -> io::Result<()> {
@@ -195,79 +372,288 @@ impl Editor {
                     self.minibuffer.set_prompt("Search: ");
                     self.state = State::Search { backward: false };
                 }
+                Key::Escape => {
+                    self.minibuffer.set_message("ESC-");
+                    self.state = State::Meta;
+                }
+                Key::Ctrl(b'Q') if self.buffers[self.current].read_only => {
+                    self.minibuffer.set_message("Buffer is read-only");
+                }
+                Key::Ctrl(b'Q') => {
+                    self.minibuffer.set_message("C-q-");
+                    self.state = State::QuotedInsert { code: 0, digits: 0 };
+                }
                 Key::Ctrl(b'X') => {
-                    self.minibuffer.set_message("C-x [C-s: save] [C-c: quit]");
+                    self.minibuffer.set_message(
+                        "C-x [C-s: save] [C-c: quit] [Left/Right: switch buffer] [n: line numbers] [w: wrap] [l: cursor line] [a: ambiguous width] [8 RET: insert char]",
+                    );
                     self.state = State::CtrlX;
                 }
+                Key::Alt(b'g') => {
+                    self.minibuffer
+                        .set_message("M-g [g: goto line] [c: goto char]");
+                    self.state = State::AltG;
+                }
+                Key::Ctrl(b'Z') => self.suspend()?,
+                Key::Char('q') if self.is_dired() || self.buffers[self.current].read_only => {
+                    self.close_buffer()
+                }
+                Key::F(..) | Key::Insert(_) | Key::Combo(..) => {
+                    self.minibuffer
+                        .set_message(&format!("{} is undefined", key));
+                }
+                Key::Enter(_) | Key::Ctrl(b'J') if self.is_dired() => self.dired_visit(),
+                Key::Char('^') if self.is_dired() => self.dired_up(),
+                Key::Char('d') if self.is_dired() => self.buffers[self.current].dired_mark(true),
+                Key::Char('u') if self.is_dired() => self.buffers[self.current].dired_mark(false),
+                Key::Char('x') if self.is_dired() => self.dired_delete(),
+                Key::Char('g') if self.is_dired() => {
+                    if let Err(e) = self.buffers[self.current].reload_dired() {
+                        self.minibuffer.set_message(&e.to_string());
+                    }
+                }
                 _ => {
-                    let message = self.buffer.process_key(key, &mut self.clipboard);
+                    let message = self.buffers[self.current].process_key(key, &mut self.clipboard);
                     self.minibuffer.set_message(message);
                 }
             },
+            State::Meta => {
+                self.minibuffer.set_message("");
+                self.state = State::Default;
+                if !matches!(key, Key::Escape | Key::Ctrl(b'G')) {
+                    self.process_key(key.with_alt())?;
+                }
+            }
+            // like emacs, up to three octal digits give a character code,
+            // ended early by RET or any other key, which is then processed
+            State::QuotedInsert { code, digits } => match key {
+                Key::Char(ch @ '0'..='7') if digits < 2 => {
+                    let code = code * 8 + ch.to_digit(8).unwrap();
+                    self.minibuffer.set_message(&format!("C-q {:o}-", code));
+                    self.state = State::QuotedInsert {
+                        code,
+                        digits: digits + 1,
+                    };
+                }
+                Key::Char(ch @ '0'..='7') => {
+                    let code = code * 8 + ch.to_digit(8).unwrap();
+                    self.quoted_insert(char::from_u32(code).unwrap());
+                }
+                _ if digits > 0 => {
+                    self.quoted_insert(char::from_u32(code).unwrap());
+                    if !matches!(key, Key::Enter(_) | Key::Ctrl(b'J')) {
+                        self.process_key(key)?;
+                    }
+                }
+                _ => match key.as_char() {
+                    Some(ch) => self.quoted_insert(ch),
+                    None => {
+                        self.minibuffer
+                            .set_message(&format!("{} cannot be inserted", key));
+                        self.state = State::Default;
+                    }
+                },
+            },
             State::Search { backward } => match key {
                 Key::Ctrl(b'G') => {
-                    self.buffer.clear_matches(true);
+                    self.buffers[self.current].clear_matches(true);
                     self.minibuffer.set_message("");
                     self.state = State::Default;
                 }
-                Key::Ctrl(b'J' | b'M') => {
-                    self.buffer.clear_matches(false);
+                Key::Enter(_) | Key::Ctrl(b'J') => {
+                    self.buffers[self.current].clear_matches(false);
                     self.minibuffer.set_message("");
                     self.state = State::Default;
                 }
                 Key::Ctrl(b'R') => {
-                    self.buffer.next_match(true);
+                    self.buffers[self.current].next_match(true);
                 }
                 Key::Ctrl(b'S') => {
-                    self.buffer.next_match(false);
+                    self.buffers[self.current].next_match(false);
                 }
                 _ => {
                     let prev_input = self.minibuffer.get_input();
                     self.minibuffer.process_key(key);
                     let input = self.minibuffer.get_input();
                     if input != prev_input {
-                        self.buffer.clear_matches(true);
-                        self.buffer.search(&input, backward);
+                        self.buffers[self.current].clear_matches(true);
+                        self.buffers[self.current].search(&input, backward);
                     }
                 }
             },
             State::CtrlX => match key {
                 Key::Ctrl(b'S') => {
-                    if self.buffer.filename.is_none() {
+                    if self.is_dired() {
+                        self.minibuffer
+                            .set_message("Cannot save a directory listing");
+                        self.state = State::Default;
+                    } else if self.buffers[self.current].filename.is_none() {
                         self.minibuffer.set_prompt("Save as: ");
                         self.state = State::Save;
                     } else {
-                        self.buffer.save()?;
-                        self.minibuffer.set_message("Saved");
-                        self.state = State::Default;
+                        self.save();
                     }
                 }
-                Key::Ctrl(b'C') => {
-                    if self.buffer.modified() {
-                        self.minibuffer
-                            .set_prompt("Quit without saving changes? (Y/n): ");
-                        self.state = State::Quit;
+                Key::Ctrl(b'C') => self.quit(),
+                Key::Ctrl(b'Q') => {
+                    let message = self.buffers[self.current].toggle_read_only();
+                    self.minibuffer.set_message(message);
+                    self.state = State::Default;
+                }
+                Key::ArrowLeft(_) => {
+                    self.current = (self.current + self.buffers.len() - 1) % self.buffers.len();
+                    self.buffers[self.current].redraw();
+                    self.minibuffer.set_message("");
+                    self.state = State::Default;
+                }
+                Key::ArrowRight(_) => {
+                    self.current = (self.current + 1) % self.buffers.len();
+                    self.buffers[self.current].redraw();
+                    self.minibuffer.set_message("");
+                    self.state = State::Default;
+                }
+                Key::Char('n') => {
+                    let message = self.buffers[self.current].toggle_line_numbers();
+                    self.minibuffer.set_message(message);
+                    self.state = State::Default;
+                }
+                Key::Char('w') => {
+                    let message = self.buffers[self.current].toggle_wrap();
+                    self.minibuffer.set_message(message);
+                    self.state = State::Default;
+                }
+                Key::Char('l') => {
+                    let message = self.buffers[self.current].toggle_cursor_line();
+                    self.minibuffer.set_message(message);
+                    self.state = State::Default;
+                }
+                Key::Char('a') => self.toggle_ambiguous_width(),
+                Key::Char('=') => {
+                    let message = self.buffers[self.current].describe_char();
+                    self.minibuffer.set_message(&message);
+                    self.state = State::Default;
+                }
+                Key::Char('8') => {
+                    self.minibuffer.set_message("C-x 8-");
+                    self.state = State::CtrlX8;
+                }
+                _ => {
+                    self.minibuffer.set_message("");
+                    self.state = State::Default;
+                }
+            },
+            State::CtrlX8 => match key {
+                Key::Enter(_) | Key::Ctrl(b'J') if self.buffers[self.current].read_only => {
+                    self.minibuffer.set_message("Buffer is read-only");
+                    self.state = State::Default;
+                }
+                Key::Enter(_) | Key::Ctrl(b'J') => {
+                    self.minibuffer
+                        .set_prompt("Insert character (Unicode name or hex): ");
+                    self.state = State::InsertChar;
+                }
+                _ => {
+                    self.minibuffer.set_message("");
+                    self.state = State::Default;
+                }
+            },
+            State::AltG => match key {
+                Key::Char('g') | Key::Alt(b'g') => {
+                    self.minibuffer.set_prompt("Goto line: ");
+                    self.state = State::GotoLine;
+                }
+                Key::Char('c') | Key::Alt(b'c') => {
+                    self.minibuffer.set_prompt("Goto char: ");
+                    self.state = State::GotoChar;
+                }
+                _ => {
+                    self.minibuffer.set_message("");
+                    self.state = State::Default;
+                }
+            },
+            State::GotoLine => match key {
+                Key::Ctrl(b'G') => {
+                    self.minibuffer.set_message("");
+                    self.state = State::Default;
+                }
+                Key::Enter(_) | Key::Ctrl(b'J') => {
+                    let input = self.minibuffer.get_input();
+                    if let Some((line, col)) = parse_line_col(input.trim()) {
+                        self.buffers[self.current].goto_line(line, col.unwrap_or(1));
+                        self.minibuffer.set_message("");
                     } else {
-                        self.state = State::Quitted;
+                        self.minibuffer.set_message("Invalid line number");
                     }
+                    self.state = State::Default;
                 }
-                _ => {
+                _ => self.minibuffer.process_key(key),
+            },
+            State::GotoChar => match key {
+                Key::Ctrl(b'G') => {
+                    self.minibuffer.set_message("");
+                    self.state = State::Default;
+                }
+                Key::Enter(_) | Key::Ctrl(b'J') => {
+                    let input = self.minibuffer.get_input();
+                    if let Ok(n) = input.trim().parse() {
+                        self.buffers[self.current].goto_char(n);
+                        self.minibuffer.set_message("");
+                    } else {
+                        self.minibuffer.set_message("Invalid character offset");
+                    }
+                    self.state = State::Default;
+                }
+                _ => self.minibuffer.process_key(key),
+            },
+            State::InsertChar => match key {
+                Key::Ctrl(b'G') => {
                     self.minibuffer.set_message("");
                     self.state = State::Default;
                 }
+                Key::Enter(_) | Key::Ctrl(b'J') => {
+                    let input = self.minibuffer.get_input();
+                    if let Some(ch) = charname::parse(&input) {
+                        let message = self.buffers[self.current].insert_char(ch);
+                        self.minibuffer.set_message(message);
+                    } else {
+                        self.minibuffer.set_message("Invalid character");
+                    }
+                    self.state = State::Default;
+                }
+                Key::Tab(Mod::NONE) => self.minibuffer.complete(charname::complete),
+                _ => self.minibuffer.process_key(key),
             },
             State::Save => match key {
                 Key::Ctrl(b'G') => {
                     self.minibuffer.set_message("");
                     self.state = State::Default;
                 }
-                Key::Ctrl(b'J' | b'M') => {
+                Key::Enter(_) | Key::Ctrl(b'J') => {
                     let input = self.minibuffer.get_input();
-                    self.buffer.filename = Some(input);
-                    self.buffer.save()?;
+                    self.buffers[self.current].filename = Some(input);
+                    self.save();
+                }
+                _ => self.minibuffer.process_key(key),
+            },
+            State::DiredDelete => match key {
+                Key::Ctrl(b'G') => {
                     self.minibuffer.set_message("");
                     self.state = State::Default;
                 }
+                Key::Enter(_) | Key::Ctrl(b'J') => {
+                    let input = self.minibuffer.get_input();
+                    if input.to_lowercase() == "y" {
+                        let buffer = &mut self.buffers[self.current];
+                        let result = buffer.dired.as_ref().unwrap().delete_marked();
+                        match result.and(buffer.reload_dired()) {
+                            Ok(()) => self.minibuffer.set_message("Deleted"),
+                            Err(e) => self.minibuffer.set_message(&e.to_string()),
+                        }
+                    } else {
+                        self.minibuffer.set_message("");
+                    }
+                    self.state = State::Default;
+                }
                 _ => self.minibuffer.process_key(key),
             },
             State::Quit => match key {
@@ -275,7 +661,7 @@ impl Editor {
                     self.minibuffer.set_message("");
                     self.state = State::Default;
                 }
-                Key::Ctrl(b'J' | b'M') => {
+                Key::Enter(_) | Key::Ctrl(b'J') => {
                     let input = self.minibuffer.get_input();
                     if input.is_empty() || input.to_lowercase() == "y" {
                         self.state = State::Quitted;
@@ -290,12 +676,176 @@ impl Editor {
         }
         Ok(())
     }
+
+    fn emergency_save(&self) -> io::Result<()> {
+        let mut result = Ok(());
+        for buffer in self.buffers.iter() {
+            if let Err(e) = buffer.emergency_save() {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn suspend(&mut self) -> io::Result<()> {
+        self.pop_keyboard_mode()?;
+        // switch to main screen buffer
+        self.stdout.write(b"\x1b[?1049l")?;
+        self.stdout.flush()?;
+        self.raw_mode.disable()?;
+
+        // execution resumes here on SIGCONT
+        signal_hook::low_level::raise(SIGSTOP)?;
+
+        self.raw_mode.enable()?;
+        // switch to alternate screen buffer
+        self.stdout.write(b"\x1b[?1049h")?;
+        self.push_keyboard_mode()?;
+        self.resize()
+    }
+
+    fn is_dired(&self) -> bool {
+        self.buffers[self.current].dired.is_some()
+    }
+
+    fn dired_visit(&mut self) {
+        let buffer = &mut self.buffers[self.current];
+        let path = match (buffer.dired.as_ref(), buffer.dired_entry()) {
+            (Some(_), Some(entry)) if entry.name == ".." => return self.dired_up(),
+            (Some(dired), Some(entry)) => dired.path.join(&entry.name),
+            _ => return,
+        };
+
+        // follows symlinks to directories
+        if path.is_dir() {
+            match Dired::read(&path) {
+                Ok(dired) => {
+                    buffer.set_dired(dired, None);
+                    self.minibuffer.set_message("");
+                }
+                Err(e) => self.minibuffer.set_message(&e.to_string()),
+            }
+            return;
+        }
+
+        let filename = path.display().to_string();
+        if let Some(i) = self
+            .buffers
+            .iter()
+            .position(|b| b.filename.as_deref() == Some(&filename))
+        {
+            self.current = i;
+            self.buffers[i].redraw();
+            self.minibuffer.set_message("");
+            return;
+        }
+
+        match Buffer::new(Some(filename)) {
+            Ok(mut buffer) => {
+                buffer.resize(Pos::new(0, 0), self.size);
+                let message = if buffer.is_lazy() {
+                    LARGE_FILE_WARNING
+                } else {
+                    ""
+                };
+                self.buffers.push(buffer);
+                self.current = self.buffers.len() - 1;
+                self.minibuffer.set_message(message);
+            }
+            Err(e) => self.minibuffer.set_message(&e.to_string()),
+        }
+    }
+
+    fn dired_up(&mut self) {
+        let buffer = &mut self.buffers[self.current];
+        let path = match buffer.dired.as_ref() {
+            Some(dired) => dired.path.clone(),
+            None => return,
+        };
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => return,
+        };
+        let name = path.file_name().map(|s| s.to_string_lossy().into_owned());
+
+        match Dired::read(parent) {
+            Ok(dired) => {
+                buffer.set_dired(dired, name.as_deref());
+                self.minibuffer.set_message("");
+            }
+            Err(e) => self.minibuffer.set_message(&e.to_string()),
+        }
+    }
+
+    fn dired_delete(&mut self) {
+        let n = match self.buffers[self.current].dired.as_ref() {
+            Some(dired) => dired.marked().len(),
+            None => return,
+        };
+        if n == 0 {
+            self.minibuffer.set_message("No files marked for deletion");
+        } else {
+            self.minibuffer
+                .set_prompt(&format!("Delete {} marked file(s)? (y/N): ", n));
+            self.state = State::DiredDelete;
+        }
+    }
+
+    // quits if it is the last buffer, and keeps one with unsaved changes
+    fn close_buffer(&mut self) {
+        if self.buffers.len() == 1 {
+            return self.quit();
+        }
+        if self.buffers[self.current].modified() {
+            return self.minibuffer.set_message("Buffer has unsaved changes");
+        }
+        self.buffers.remove(self.current);
+        self.current = self.current.saturating_sub(1);
+        self.buffers[self.current].redraw();
+        self.minibuffer.set_message("");
+    }
+
+    fn quoted_insert(&mut self, ch: char) {
+        let message = self.buffers[self.current].insert_char(ch);
+        self.minibuffer.set_message(message);
+        self.state = State::Default;
+    }
+
+    // a failed save leaves the buffer as it was, so it is only reported
+    fn save(&mut self) {
+        match self.buffers[self.current].save() {
+            Ok(()) => self.minibuffer.set_message("Saved"),
+            Err(e) => self.minibuffer.set_message(&format!("Cannot save: {}", e)),
+        }
+        self.state = State::Default;
+    }
+
+    fn quit(&mut self) {
+        if self.buffers.iter().any(|b| b.modified()) {
+            self.minibuffer
+                .set_prompt("Quit without saving changes? (Y/n): ");
+            self.state = State::Quit;
+        } else {
+            self.state = State::Quitted;
+        }
+    }
+}
+
+// parses "\x1b[{row};{col}R"
+fn parse_cursor_report(bytes: &[u8]) -> Option<(usize, usize)> {
+    let s = str::from_utf8(bytes).ok()?;
+    let (row, col) = s
+        .strip_prefix("\x1b[")?
+        .strip_suffix('R')?
+        .split_once(';')?;
+    Some((row.parse().ok()?, col.parse().ok()?))
 }
 
 impl Drop for Editor {
     fn drop(&mut self) {
-        // switch to main screen buffer
-        self.stdout.write(b"\x1b[?1049l").unwrap();
-        self.stdout.flush().unwrap();
+        // switch to main screen buffer; the terminal may be gone after SIGHUP
+        let _ = self.pop_keyboard_mode();
+        let _ = self.stdout.write(b"\x1b[?1049l");
+        let _ = self.stdout.flush();
     }
 }
@@ -33,6 +33,16 @@ impl Event {
         }
     }
 
+    pub fn map_pos<F: Fn(Pos) -> Pos>(self, f: F) -> Self {
+        match self {
+            Self::Insert(id, pos, string) => Self::Insert(id, f(pos), string),
+            Self::InsertMv(id, pos, string) => Self::InsertMv(id, f(pos), string),
+            Self::Remove(id, pos1, pos2) => Self::Remove(id, f(pos1), f(pos2)),
+            Self::RemoveMv(id, pos1, pos2) => Self::RemoveMv(id, f(pos1), f(pos2)),
+            Self::Indent(id, pos, string) => Self::Indent(id, f(pos), string),
+        }
+    }
+
     pub fn id(&self) -> usize {
         match self {
             Self::Insert(id, ..) => *id,
@@ -1,4 +1,6 @@
-#[derive(Clone, Copy, PartialEq)]
+use std::ops::{BitOr, Range};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Fg {
     Default,
     Keyword,
@@ -12,13 +14,93 @@ pub enum Fg {
     Prompt,
     Match,
     CurrentMatch,
+    LineNumber,
+    CurrentLineNumber,
+    Escape,
+    Error,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Bg {
     Default,
     Region,
     StatusBar,
     Match,
     CurrentMatch,
+    CurrentLine,
+}
+
+// syntax faces of a row as runs, each starting at a byte index; the row
+// begins plain
+#[derive(Default)]
+pub struct Faces(Vec<(usize, Fg)>);
+
+impl Faces {
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    // sets the face from idx onwards; runs must be pushed in order
+    pub fn push(&mut self, idx: usize, fg: Fg) {
+        if let Some(&(start, _)) = self.0.last() {
+            if start == idx {
+                self.0.pop();
+            }
+        }
+        if fg != self.0.last().map_or(Fg::Default, |&(_, fg)| fg) {
+            self.0.push((idx, fg));
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Fg {
+        match self.0.partition_point(|&(start, _)| start <= idx) {
+            0 => Fg::Default,
+            i => self.0[i - 1].1,
+        }
+    }
+}
+
+// a face laid over the syntax faces when a row is drawn, such as the region
+// or a search match; the columns may reach past the end of the row
+pub struct Overlay {
+    pub x_range: Range<usize>,
+    pub fg: Option<Fg>,
+    pub bg: Bg,
+}
+
+impl Overlay {
+    // the face at column x, the last overlay covering it winning
+    pub fn merge(overlays: &[Overlay], x: usize, fg: Fg) -> (Fg, Bg) {
+        overlays
+            .iter()
+            .filter(|overlay| overlay.x_range.contains(&x))
+            .fold((fg, Bg::Default), |(fg, _), overlay| {
+                (overlay.fg.unwrap_or(fg), overlay.bg)
+            })
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Attr(u8);
+
+impl Attr {
+    pub const NONE: Self = Self(0);
+    pub const BOLD: Self = Self(1 << 0);
+    pub const ITALIC: Self = Self(1 << 1);
+    pub const UNDERLINE: Self = Self(1 << 2);
+    pub const REVERSE: Self = Self(1 << 3);
+
+    #[inline]
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Attr {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
 }
@@ -0,0 +1,82 @@
+use std::io::{self, Write};
+
+use crate::canvas::Canvas;
+use crate::face::{Bg, Fg};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative,
+}
+
+pub struct Gutter {
+    pub line_numbers: LineNumbers,
+    pub width: usize,
+    pub cursor_y: usize,
+}
+
+impl Gutter {
+    pub fn new() -> Self {
+        Self {
+            line_numbers: LineNumbers::Off,
+            width: 0,
+            cursor_y: 0,
+        }
+    }
+
+    pub fn toggle(&mut self) -> &'static str {
+        match self.line_numbers {
+            LineNumbers::Off => {
+                self.line_numbers = LineNumbers::Absolute;
+                "Line numbers: absolute"
+            }
+            LineNumbers::Absolute => {
+                self.line_numbers = LineNumbers::Relative;
+                "Line numbers: relative"
+            }
+            LineNumbers::Relative => {
+                self.line_numbers = LineNumbers::Off;
+                "Line numbers: off"
+            }
+        }
+    }
+
+    pub fn width_for(&self, len: usize) -> usize {
+        match self.line_numbers {
+            LineNumbers::Off => 0,
+            _ => len.to_string().len() + 1,
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, y: usize, len: usize) -> io::Result<()> {
+        if self.width == 0 {
+            return Ok(());
+        }
+        if y >= len {
+            return self.draw_blank(canvas);
+        }
+
+        let number = match self.line_numbers {
+            LineNumbers::Relative if y != self.cursor_y => y.abs_diff(self.cursor_y),
+            _ => y + 1,
+        };
+
+        canvas.set_bg_color(Bg::Default)?;
+
+        if y == self.cursor_y {
+            canvas.set_fg_color(Fg::CurrentLineNumber)?;
+        } else {
+            canvas.set_fg_color(Fg::LineNumber)?;
+        }
+        write!(canvas, "{:>1$} ", number, self.width - 1)
+    }
+
+    pub fn draw_blank(&self, canvas: &mut Canvas) -> io::Result<()> {
+        if self.width > 0 {
+            canvas.set_bg_color(Bg::Default)?;
+            canvas.write_repeat(b" ", self.width)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str;
+use std::time::Duration;
+
+use crate::key::{Key, KeyError, Mod};
+
+// how often a blocked read_key checks whether it was interrupted
+const TICK: Duration = Duration::from_millis(100);
+
+// how long to wait for the rest of an escape sequence, see ELE_ESCDELAY
+const ESC_TIMEOUT: Duration = Duration::from_millis(100);
+
+// how long to wait for each byte of a terminal reply
+pub const REPLY_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct Input {
+    tty: File,
+    buf: VecDeque<u8>,
+    esc_timeout: Duration,
+    // whether keys are reported with the kitty keyboard protocol
+    pub kitty_keyboard: bool,
+}
+
+impl Input {
+    pub fn new(tty: File) -> Self {
+        let esc_timeout = env::var("ELE_ESCDELAY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(ESC_TIMEOUT, Duration::from_millis);
+
+        Self {
+            tty,
+            buf: VecDeque::new(),
+            esc_timeout,
+            kitty_keyboard: false,
+        }
+    }
+
+    pub fn read_key<F: Fn() -> bool>(&mut self, interrupted: F) -> Result<Key, KeyError> {
+        loop {
+            if let Some(b) = self.read_byte(TICK)? {
+                return self.decode(b);
+            }
+            if interrupted() {
+                return Err(KeyError::Interrupted);
+            }
+        }
+    }
+
+    // gives up after timeout if no input is buffered or arrives
+    pub fn read_byte(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        if self.buf.is_empty() {
+            self.fill(timeout)?;
+        }
+        Ok(self.buf.pop_front())
+    }
+
+    // puts back bytes read while waiting for a terminal reply
+    pub fn unread(&mut self, bytes: &[u8]) {
+        for &b in bytes.iter().rev() {
+            self.buf.push_front(b);
+        }
+    }
+
+    fn fill(&mut self, timeout: Duration) -> io::Result<()> {
+        let mut pollfd = libc::pollfd {
+            fd: self.tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let result = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+        if result < 0 {
+            let e = io::Error::last_os_error();
+            // a signal such as SIGWINCH arrived
+            if e.kind() == io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(e);
+        }
+        if result > 0 {
+            let mut buf = [0; 1024];
+            let len = self.tty.read(&mut buf)?;
+            self.buf.extend(&buf[..len]);
+        }
+        Ok(())
+    }
+
+    fn decode(&mut self, b: u8) -> Result<Key, KeyError> {
+        match b {
+            b'\t' => Ok(Key::Tab(Mod::NONE)),
+            b'\r' => Ok(Key::Enter(Mod::NONE)),
+            0..=26 | 28..=31 => Ok(Key::Ctrl(b'@' + b)),
+            27 => self.read_escape_sequence(),
+            32..=126 => Ok(Key::Char(b as char)),
+            127 => Ok(Key::Backspace),
+            _ => match self.read_utf8(b)? {
+                Some(ch) => Ok(Key::Char(ch)),
+                None => Err(KeyError::UnknownKey),
+            },
+        }
+    }
+
+    // a lone ESC is returned as Key::Escape, which the editor treats as a
+    // Meta prefix; a key following within the timeout is decoded with Meta
+    fn read_escape_sequence(&mut self) -> Result<Key, KeyError> {
+        match self.read_byte(self.esc_timeout)? {
+            None => Ok(Key::Escape),
+            Some(b'[') => self.read_csi(),
+            Some(b'O') => match self.read_byte(self.esc_timeout)? {
+                Some(b) => Key::from_ss3(b, Mod::NONE).ok_or(KeyError::UnknownKey),
+                None => Ok(Key::Alt(b'O')),
+            },
+            Some(b) => Ok(self.decode(b)?.with_alt()),
+        }
+    }
+
+    // consumes the whole sequence even if it is not a known key
+    fn read_csi(&mut self) -> Result<Key, KeyError> {
+        let mut params = Vec::new();
+        loop {
+            match self.read_byte(self.esc_timeout)? {
+                None if params.is_empty() => return Ok(Key::Alt(b'[')),
+                None => return Err(KeyError::UnknownKey),
+                // parameter and intermediate bytes
+                Some(b @ 0x20..=0x3f) => params.push(b),
+                Some(b @ 0x40..=0x7e) => {
+                    return Key::from_csi(&params, b, self.kitty_keyboard)
+                        .ok_or(KeyError::UnknownKey)
+                }
+                Some(_) => return Err(KeyError::UnknownKey),
+            }
+        }
+    }
+
+    fn read_utf8(&mut self, first_byte: u8) -> io::Result<Option<char>> {
+        let mut buf = vec![first_byte];
+
+        while buf.len() < 4 {
+            match self.read_byte(self.esc_timeout)? {
+                Some(b) => buf.push(b),
+                None => break,
+            }
+            if let Ok(s) = str::from_utf8(&buf) {
+                return Ok(s.chars().next());
+            }
+        }
+        Ok(None)
+    }
+}
+
+// checks for "\x1b[?{params}{final}"
+pub fn is_private_reply(bytes: &[u8]) -> bool {
+    match bytes.strip_prefix(b"\x1b[?") {
+        Some([params @ .., _]) => params.iter().all(|&b| b.is_ascii_digit() || b == b';'),
+        _ => false,
+    }
+}
+
+impl AsRawFd for Input {
+    fn as_raw_fd(&self) -> RawFd {
+        self.tty.as_raw_fd()
+    }
+}
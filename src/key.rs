@@ -1,20 +1,261 @@
+use std::fmt;
 use std::io;
+use std::ops::BitOr;
+use std::str;
 
 pub enum Key {
-    ArrowLeft,
-    ArrowRight,
-    ArrowUp,
-    ArrowDown,
-    Home,
-    End,
-    PageUp,
-    PageDown,
+    ArrowLeft(Mod),
+    ArrowRight(Mod),
+    ArrowUp(Mod),
+    ArrowDown(Mod),
+    Home(Mod),
+    End(Mod),
+    PageUp(Mod),
+    PageDown(Mod),
+    Insert(Mod),
+    Delete(Mod),
+    F(u8, Mod),
+    Tab(Mod),
+    Enter(Mod),
     Backspace,
-    Delete,
     Escape,
     Ctrl(u8),
     Alt(u8),
     Char(char),
+    // a modified character with no legacy encoding, e.g. C-S-a
+    Combo(Mod, char),
+}
+
+impl Key {
+    // modifiers of keys bound to cursor motions in Buffer::process_key
+    pub fn motion_mod(&self) -> Option<Mod> {
+        match *self {
+            Self::ArrowLeft(m)
+            | Self::ArrowRight(m)
+            | Self::ArrowUp(m)
+            | Self::ArrowDown(m)
+            | Self::Home(m)
+            | Self::End(m)
+            | Self::PageUp(m)
+            | Self::PageDown(m) => Some(m),
+            Self::Ctrl(b'A' | b'B' | b'E' | b'F' | b'N' | b'P' | b'V')
+            | Self::Alt(b'<' | b'>' | b'b' | b'f' | b'v') => Some(Mod::NONE),
+            _ => None,
+        }
+    }
+
+    // the character a key sends in a legacy terminal, e.g. '\x01' for C-a
+    pub fn as_char(&self) -> Option<char> {
+        match *self {
+            Self::Tab(Mod::NONE) => Some('\t'),
+            Self::Enter(Mod::NONE) => Some('\r'),
+            Self::Backspace => Some('\x7f'),
+            Self::Escape => Some('\x1b'),
+            Self::Ctrl(b) => Some((b & 0x1f) as char),
+            Self::Char(ch) => Some(ch),
+            _ => None,
+        }
+    }
+
+    // the key pressed after an ESC prefix, e.g. M-x for ESC x
+    pub fn with_alt(self) -> Self {
+        match self {
+            Self::ArrowLeft(m) => Self::ArrowLeft(m | Mod::ALT),
+            Self::ArrowRight(m) => Self::ArrowRight(m | Mod::ALT),
+            Self::ArrowUp(m) => Self::ArrowUp(m | Mod::ALT),
+            Self::ArrowDown(m) => Self::ArrowDown(m | Mod::ALT),
+            Self::Home(m) => Self::Home(m | Mod::ALT),
+            Self::End(m) => Self::End(m | Mod::ALT),
+            Self::PageUp(m) => Self::PageUp(m | Mod::ALT),
+            Self::PageDown(m) => Self::PageDown(m | Mod::ALT),
+            Self::Insert(m) => Self::Insert(m | Mod::ALT),
+            Self::Delete(m) => Self::Delete(m | Mod::ALT),
+            Self::F(n, m) => Self::F(n, m | Mod::ALT),
+            Self::Tab(m) => Self::Tab(m | Mod::ALT),
+            Self::Enter(m) => Self::Enter(m | Mod::ALT),
+            Self::Backspace => Self::Alt(127),
+            Self::Ctrl(b) => Self::Combo(Mod::CTRL | Mod::ALT, b.to_ascii_lowercase() as char),
+            Self::Char(ch) if ch.is_ascii() => Self::Alt(ch as u8),
+            Self::Char(ch) => Self::Combo(Mod::ALT, ch),
+            Self::Combo(m, ch) => Self::Combo(m | Mod::ALT, ch),
+            key @ (Self::Escape | Self::Alt(_)) => key,
+        }
+    }
+
+    // decodes "ESC [ params final", e.g. "ESC [ 1 ; 5 C" for C-<right>,
+    // with kitty set when the kitty keyboard protocol is enabled
+    pub fn from_csi(params: &[u8], final_byte: u8, kitty: bool) -> Option<Self> {
+        if !params
+            .iter()
+            .all(|&b| b.is_ascii_digit() || b == b';' || b == b':')
+        {
+            return None;
+        }
+        // sub-parameters after ':' are ignored except for the shifted key
+        // of kitty alternate keys, e.g. "45:95" for S-- giving '_'
+        let params = str::from_utf8(params).ok()?;
+        let mut params = params
+            .split(';')
+            .map(|s| s.split(':').map(|s| s.parse::<u32>().ok()));
+        let mut key = params.next()?;
+        let code = key.next().flatten().unwrap_or(1);
+        let shifted = key.next().flatten();
+        let m = params.next().and_then(|mut m| m.next()).flatten();
+        let m = Mod::from_param(m.unwrap_or(1), kitty);
+
+        match final_byte {
+            b'u' => Self::from_kitty(code, shifted, m),
+            b'~' => match code {
+                1 | 7 => Some(Self::Home(m)),
+                2 => Some(Self::Insert(m)),
+                3 => Some(Self::Delete(m)),
+                4 | 8 => Some(Self::End(m)),
+                5 => Some(Self::PageUp(m)),
+                6 => Some(Self::PageDown(m)),
+                11..=15 => Some(Self::F(code as u8 - 10, m)),
+                17..=21 => Some(Self::F(code as u8 - 11, m)),
+                23..=26 => Some(Self::F(code as u8 - 12, m)),
+                _ => None,
+            },
+            _ => Self::from_ss3(final_byte, m),
+        }
+    }
+
+    // decodes "ESC O final", also used for CSI sequences with the same final byte
+    pub fn from_ss3(final_byte: u8, m: Mod) -> Option<Self> {
+        match final_byte {
+            b'A' => Some(Self::ArrowUp(m)),
+            b'B' => Some(Self::ArrowDown(m)),
+            b'C' => Some(Self::ArrowRight(m)),
+            b'D' => Some(Self::ArrowLeft(m)),
+            b'H' => Some(Self::Home(m)),
+            b'F' => Some(Self::End(m)),
+            b'P'..=b'S' => Some(Self::F(final_byte - b'P' + 1, m)),
+            b'Z' => Some(Self::Tab(Mod::SHIFT)),
+            _ => None,
+        }
+    }
+
+    // decodes "ESC [ code ; mods u" from the kitty keyboard protocol
+    fn from_kitty(code: u32, shifted: Option<u32>, m: Mod) -> Option<Self> {
+        match code {
+            9 => return Some(Self::Tab(m)),
+            13 => return Some(Self::Enter(m)),
+            27 => return Some(Self::Escape),
+            127 => return Some(Self::Backspace),
+            // functional keys without a legacy encoding, e.g. keypad keys
+            57344..=63743 => return None,
+            _ => (),
+        }
+
+        // the code is the unshifted key, e.g. 'a' for M-A, and the shifted
+        // one is used for other symbols, e.g. '_' for C-S--, which is C-_
+        let ch = char::from_u32(code)?;
+        let (ch, m) = match shifted.and_then(char::from_u32) {
+            Some(shifted) if m.contains(Mod::SHIFT) && shifted != ch.to_ascii_uppercase() => {
+                (shifted, Mod(m.0 & !Mod::SHIFT.0))
+            }
+            _ => (ch, m),
+        };
+        let shifted = ch.to_ascii_uppercase();
+
+        match m {
+            Mod::NONE => Some(Self::Char(ch)),
+            Mod::SHIFT => Some(Self::Char(shifted)),
+            Mod::ALT if ch.is_ascii() => Some(Self::Alt(ch as u8)),
+            m if m == Mod::ALT | Mod::SHIFT && ch.is_ascii() => Some(Self::Alt(shifted as u8)),
+            Mod::CTRL => match ch {
+                ' ' | '@' => Some(Self::Ctrl(b'@')),
+                '/' => Some(Self::Ctrl(b'_')),
+                'a'..='z' | '['..='_' => Some(Self::Ctrl(shifted as u8)),
+                _ => Some(Self::Combo(m, ch)),
+            },
+            _ => Some(Self::Combo(m, ch)),
+        }
+    }
+}
+
+// emacs-style key names, e.g. "C-<right>" or "<f5>"
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, m) = match self {
+            Self::ArrowLeft(m) => ("left", m),
+            Self::ArrowRight(m) => ("right", m),
+            Self::ArrowUp(m) => ("up", m),
+            Self::ArrowDown(m) => ("down", m),
+            Self::Home(m) => ("home", m),
+            Self::End(m) => ("end", m),
+            Self::PageUp(m) => ("prior", m),
+            Self::PageDown(m) => ("next", m),
+            Self::Insert(m) => ("insert", m),
+            Self::Delete(m) => ("delete", m),
+            Self::F(n, m) => return write!(f, "{}<f{}>", m, n),
+            Self::Tab(Mod::SHIFT) => return write!(f, "<backtab>"),
+            Self::Tab(m) => return write!(f, "{}TAB", m),
+            Self::Enter(m) => return write!(f, "{}RET", m),
+            Self::Backspace => return write!(f, "DEL"),
+            Self::Escape => return write!(f, "ESC"),
+            Self::Ctrl(b) => return write!(f, "C-{}", b.to_ascii_lowercase() as char),
+            Self::Alt(b) => return write!(f, "M-{}", *b as char),
+            Self::Char(ch) => return write!(f, "{}", ch),
+            Self::Combo(m, ch) => return write!(f, "{}{}", m, ch),
+        };
+        write!(f, "{}<{}>", m, name)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Mod(u8);
+
+impl Mod {
+    pub const NONE: Self = Self(0);
+    pub const SHIFT: Self = Self(1 << 0);
+    pub const ALT: Self = Self(1 << 1);
+    pub const CTRL: Self = Self(1 << 2);
+    pub const SUPER: Self = Self(1 << 3);
+
+    #[inline]
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // xterm encodes modifiers as 1 + bitmask, with meta (8) treated as alt;
+    // kitty uses 8 for super and 32 for meta, and reports lock keys above
+    fn from_param(param: u32, kitty: bool) -> Self {
+        let bits = param.saturating_sub(1);
+        let (meta, sup) = if kitty {
+            (0b100000, 0b1000)
+        } else {
+            (0b1000, 0)
+        };
+        let m = |bit, m: Self| if bits & bit != 0 { m.0 } else { 0 };
+        Self(bits as u8 & 0b111 | m(meta, Self::ALT) | m(sup, Self::SUPER))
+    }
+}
+
+impl BitOr for Mod {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Mod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (m, prefix) in [
+            (Self::CTRL, "C-"),
+            (Self::ALT, "M-"),
+            (Self::SHIFT, "S-"),
+            (Self::SUPER, "s-"),
+        ] {
+            if self.0 & m.0 != 0 {
+                f.write_str(prefix)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 pub enum KeyError {
@@ -28,3 +269,37 @@ impl From<io::Error> for KeyError {
         Self::IoError(error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csi(params: &str, final_byte: u8, kitty: bool) -> String {
+        Key::from_csi(params.as_bytes(), final_byte, kitty)
+            .map_or("none".into(), |key| key.to_string())
+    }
+
+    #[test]
+    fn kitty_ctrl_underscore() {
+        // C-S-- with the shifted key, and C-/ as sent by other terminals
+        assert_eq!(csi("45:95;6", b'u', true), "C-_");
+        assert_eq!(csi("47;5", b'u', true), "C-_");
+        // without the shifted key the key is not guessed from a layout
+        assert_eq!(csi("45;6", b'u', true), "C-S--");
+        // shifted letters keep their own encodings
+        assert_eq!(csi("97:65;4", b'u', true), "M-A");
+        assert_eq!(csi("97:65;6", b'u', true), "C-S-a");
+    }
+
+    #[test]
+    fn kitty_super_is_not_alt() {
+        assert_eq!(csi("120;9", b'u', true), "s-x");
+        assert_eq!(csi("120;3", b'u', true), "M-x");
+        assert_eq!(csi("1;9", b'A', true), "s-<up>");
+        // meta is alt, and lock keys are ignored
+        assert_eq!(csi("120;33", b'u', true), "M-x");
+        assert_eq!(csi("97;197", b'u', true), "C-a");
+        // xterm reports meta as 8
+        assert_eq!(csi("1;9", b'A', false), "M-<up>");
+    }
+}
@@ -3,12 +3,17 @@
 #[macro_use]
 mod color;
 
+mod args;
 mod buffer;
 mod canvas;
+mod charname;
 mod coord;
+mod dired;
 mod editor;
 mod event;
 mod face;
+mod gutter;
+mod input;
 mod key;
 mod minibuffer;
 mod raw_mode;
@@ -18,17 +23,45 @@ mod syntax;
 mod util;
 
 use std::env;
-use std::io;
+use std::fs::OpenOptions;
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::process;
 
+use crate::args::{Command, USAGE};
 use crate::editor::Editor;
 use crate::raw_mode::RawMode;
 
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let args = match Command::parse(env::args().skip(1)) {
+        Ok(Command::Edit(args)) => args,
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+        Ok(Command::Version) => {
+            println!("ele {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(message) => {
+            eprintln!("ele: {}", message);
+            eprintln!("Try 'ele --help' for more information.");
+            process::exit(2);
+        }
+    };
 
-    let raw_mode = RawMode::new()?;
+    // read a piped file before raw mode, so that C-c can interrupt it
+    let mut stdin = Vec::new();
+    if args.files.iter().any(|file| file.filename.is_none()) {
+        io::stdin().lock().read_to_end(&mut stdin)?;
+    }
+
+    // read keys from the terminal even if stdin is a pipe
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+
+    let raw_mode = RawMode::new(tty.as_raw_fd())?;
     raw_mode.enable()?;
 
-    let mut editor = Editor::new(args.get(1).cloned())?;
+    let mut editor = Editor::new(args, &stdin, tty.try_clone()?, raw_mode)?;
     editor.run()
 }
@@ -2,8 +2,8 @@ use std::io::{self, Write};
 
 use crate::canvas::Canvas;
 use crate::coord::{Pos, Size};
-use crate::face::{Bg, Fg};
-use crate::key::Key;
+use crate::face::Fg;
+use crate::key::{Key, Mod};
 use crate::row::Row;
 
 pub struct Minibuffer {
@@ -13,6 +13,8 @@ pub struct Minibuffer {
     cursor: usize,
     prompt_len: usize,
     row: Row,
+    // candidates cycled by repeated completion, and the one shown
+    completion: Option<(Vec<String>, usize)>,
     draw: bool,
 }
 
@@ -25,6 +27,7 @@ impl Minibuffer {
             cursor: 0,
             prompt_len: 0,
             row: Row::new(String::new()),
+            completion: None,
             draw: true,
         }
     }
@@ -36,6 +39,7 @@ impl Minibuffer {
             self.offset = 0;
             self.cursor = 0;
             self.prompt_len = 0;
+            self.completion = None;
             self.highlight();
         }
     }
@@ -46,6 +50,7 @@ impl Minibuffer {
         self.offset = 0;
         self.cursor = self.row.last_x();
         self.prompt_len = self.row.last_x();
+        self.completion = None;
         self.highlight();
     }
 
@@ -53,6 +58,52 @@ impl Minibuffer {
         self.row.string[self.prompt_len..].to_string()
     }
 
+    fn set_input(&mut self, string: &str) {
+        self.row.truncate(self.prompt_len);
+        self.row.push_str(string);
+        self.cursor = self.row.last_x();
+        self.highlight();
+        self.scroll();
+    }
+
+    // completes the input to the longest common prefix of the candidates,
+    // then cycles through them if it is already complete
+    pub fn complete<F: FnOnce(&str) -> Vec<String>>(&mut self, candidates: F) {
+        if let Some((candidates, i)) = self.completion.take() {
+            let i = (i + 1) % candidates.len();
+            self.set_input(&candidates[i]);
+            self.completion = Some((candidates, i));
+            return;
+        }
+
+        let input = self.get_input();
+        let candidates = candidates(&input);
+        let (first, last) = match (candidates.first(), candidates.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return,
+        };
+
+        // the candidates are sorted, so the first and last differ the most
+        let len = first
+            .char_indices()
+            .zip(last.chars())
+            .find(|&((_, ch1), ch2)| ch1 != ch2)
+            .map_or(first.len().min(last.len()), |((idx, _), _)| idx);
+
+        if candidates.len() > 1 && len <= input.len() {
+            self.set_input(&candidates[0]);
+            self.completion = Some((candidates, 0));
+        } else {
+            self.set_input(&first[..len]);
+        }
+    }
+
+    pub fn update_mappings(&mut self) {
+        self.row.update_mappings();
+        self.cursor = self.row.prev_fit_x(self.cursor);
+        self.draw = true;
+    }
+
     pub fn resize(&mut self, pos: Pos, size: Size) {
         self.pos = pos;
         self.size = size;
@@ -65,7 +116,7 @@ impl Minibuffer {
             let x_range = self.offset..(self.offset + self.size.w);
 
             canvas.set_cursor(self.pos.x, self.pos.y)?;
-            self.row.draw(canvas, x_range)?;
+            self.row.draw(canvas, x_range, &[])?;
             canvas.write(b"\x1b[K")?;
 
             self.draw = false;
@@ -78,20 +129,22 @@ impl Minibuffer {
     }
 
     pub fn process_key(&mut self, key: Key) {
+        self.completion = None;
+
         match key {
-            Key::ArrowLeft | Key::Ctrl(b'B') => {
+            Key::ArrowLeft(_) | Key::Ctrl(b'B') => {
                 if let Some(x) = self.row.prev_x(self.cursor) {
                     self.cursor = x;
                     self.scroll();
                 }
             }
-            Key::ArrowRight | Key::Ctrl(b'F') => {
+            Key::ArrowRight(_) | Key::Ctrl(b'F') => {
                 if let Some(x) = self.row.next_x(self.cursor) {
                     self.cursor = x;
                     self.scroll();
                 }
             }
-            Key::Home | Key::Ctrl(b'A') => {
+            Key::Home(_) | Key::Ctrl(b'A') => {
                 self.cursor = if self.cursor == self.prompt_len {
                     0
                 } else {
@@ -99,7 +152,7 @@ impl Minibuffer {
                 };
                 self.scroll();
             }
-            Key::End | Key::Ctrl(b'E') => {
+            Key::End(_) | Key::Ctrl(b'E') => {
                 self.cursor = self.row.last_x();
                 self.scroll();
             }
@@ -113,7 +166,7 @@ impl Minibuffer {
                     }
                 }
             }
-            Key::Delete | Key::Ctrl(b'D') => {
+            Key::Delete(_) | Key::Ctrl(b'D') => {
                 if self.cursor >= self.prompt_len {
                     if let Some(x) = self.row.next_x(self.cursor) {
                         self.row.remove_str(self.cursor, x);
@@ -121,7 +174,7 @@ impl Minibuffer {
                     }
                 }
             }
-            Key::Ctrl(b'I') => {
+            Key::Tab(Mod::NONE) => {
                 if self.cursor >= self.prompt_len {
                     let x = self.row.insert_str(self.cursor, "\t");
                     self.cursor = x;
@@ -157,12 +210,10 @@ impl Minibuffer {
 
     fn highlight(&mut self) {
         self.row.faces.clear();
+        self.row.faces.push(0, Fg::Prompt);
         self.row
             .faces
-            .resize(self.prompt_len, (Fg::Prompt, Bg::Default));
-        self.row
-            .faces
-            .resize(self.row.string.len(), (Fg::Default, Bg::Default));
+            .push(self.row.x_to_idx(self.prompt_len), Fg::Default);
         self.draw = true;
     }
 
@@ -1,7 +1,7 @@
 extern crate termios;
 
 use std::io;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::RawFd;
 use termios::*;
 
 pub struct RawMode {
@@ -10,8 +10,7 @@ pub struct RawMode {
 }
 
 impl RawMode {
-    pub fn new() -> io::Result<Self> {
-        let fd = io::stdin().as_raw_fd();
+    pub fn new(fd: RawFd) -> io::Result<Self> {
         let termios = Termios::from_fd(fd)?;
 
         Ok(Self { fd, termios })
@@ -29,10 +28,15 @@ impl RawMode {
 
         tcsetattr(self.fd, TCSAFLUSH, &clone)
     }
+
+    pub fn disable(&self) -> io::Result<()> {
+        tcsetattr(self.fd, TCSAFLUSH, &self.termios)
+    }
 }
 
 impl Drop for RawMode {
     fn drop(&mut self) {
-        tcsetattr(self.fd, TCSAFLUSH, &self.termios).unwrap();
+        // the terminal may be gone after SIGHUP
+        let _ = self.disable();
     }
 }
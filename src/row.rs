@@ -1,38 +1,102 @@
+extern crate unicode_segmentation;
 extern crate unicode_width;
 
+use std::cell::{Cell, Ref, RefCell};
 use std::cmp;
 use std::io::{self, Write};
 use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthChar;
 
 use crate::canvas::Canvas;
-use crate::face::{Bg, Fg};
+use crate::face::{Faces, Fg, Overlay};
 use crate::util::UintVec;
 
 const TAB_WIDTH: usize = 4;
-const ZWJ_WIDTH: usize = 1;
 const TOMBSTONE: usize = 0;
 
+thread_local! {
+    // the width of East Asian ambiguous characters, 2 on many CJK
+    // terminals; the editor has one thread, and each test its own
+    static AMBIGUOUS_WIDTH: Cell<usize> = const { Cell::new(1) };
+}
+
+// existing rows must be rebuilt with Row::update_mappings
+pub fn set_ambiguous_width(width: usize) {
+    AMBIGUOUS_WIDTH.with(|w| w.set(width));
+}
+
+pub fn ambiguous_width() -> usize {
+    AMBIGUOUS_WIDTH.with(Cell::get)
+}
+
 #[inline]
-fn char_width(x: usize, ch: char) -> usize {
+pub fn char_width(ch: char) -> Option<usize> {
+    if ambiguous_width() == 2 {
+        ch.width_cjk()
+    } else {
+        ch.width()
+    }
+}
+
+// control characters in caret notation, e.g. "^A" or "^[" for ESC, with
+// C1 controls shown as their 7-bit equivalents
+fn escape_char(ch: char) -> String {
     match ch {
-        '\t' => TAB_WIDTH - x % TAB_WIDTH,
-        '\u{200d}' => ZWJ_WIDTH,
-        _ => ch.width().unwrap_or(0),
+        '\0'..='\x1f' => format!("^{}", (ch as u8 + 0x40) as char),
+        '\x7f' => "^?".to_string(),
+        '\u{80}'..='\u{9f}' => format!("^[{}", (ch as u8 - 0x40) as char),
+        _ => format!("<U+{:04X}>", ch as u32),
+    }
+}
+
+// a cluster of control characters, e.g. CR LF, or without a visible base,
+// e.g. a lone ZWJ or combining mark, is shown as escapes such as "<U+200D>"
+fn escape_glyph(cluster: &str) -> Option<String> {
+    match cluster.chars().next()? {
+        '\t' => None,
+        ch if char_width(ch).unwrap_or(0) == 0 => Some(cluster.chars().map(escape_char).collect()),
+        _ => None,
+    }
+}
+
+#[inline]
+fn is_regional_indicator(ch: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&ch)
+}
+
+// the width of an extended grapheme cluster is that of its base character,
+// except for emoji presentation (VS16) and flags which are double width
+fn cluster_width(x: usize, cluster: &str) -> usize {
+    let ch = match cluster.chars().next() {
+        Some('\t') => return TAB_WIDTH - x % TAB_WIDTH,
+        Some(ch) => ch,
+        None => return 0,
+    };
+    if let Some(glyph) = escape_glyph(cluster) {
+        return glyph.len();
+    }
+    if cluster.len() > ch.len_utf8() && (cluster.contains('\u{fe0f}') || is_regional_indicator(ch))
+    {
+        return 2;
     }
+    char_width(ch).unwrap_or(0)
 }
 
 fn str_width(x: usize, string: &str) -> usize {
-    string.chars().fold(0, |w, ch| w + char_width(x + w, ch))
+    string
+        .graphemes(true)
+        .fold(0, |w, cluster| w + cluster_width(x + w, cluster))
 }
 
 pub struct Row {
     pub string: String,
     pub context: Option<String>,
-    pub faces: Vec<(Fg, Bg)>,
-    pub trailing_bg: Bg,
+    pub faces: Faces,
     pub indent_level: usize,
     x_to_idx: Option<Box<UintVec>>,
+    // the wrap points for the width they were found for
+    wrap_xs: RefCell<Option<(usize, Vec<usize>)>>,
 }
 
 impl Row {
@@ -40,10 +104,10 @@ impl Row {
         let mut row = Self {
             string,
             context: None,
-            faces: Vec::new(),
-            trailing_bg: Bg::Default,
+            faces: Faces::default(),
             indent_level: 0,
             x_to_idx: None,
+            wrap_xs: RefCell::new(None),
         };
         row.update_mappings();
         row
@@ -59,19 +123,34 @@ impl Row {
 
     pub fn idx_to_x(&self, idx: usize) -> usize {
         match self.x_to_idx.as_ref() {
+            // the indices grow with x apart from the tombstones inside wide
+            // clusters, so search the first x at or past idx, skipping back
+            // over tombstones to the start of their cluster
             Some(v) => {
-                let mut x = 0;
-                loop {
-                    if v.at(x) == idx {
-                        break x;
+                let mut range = 0..self.last_x();
+                while range.start < range.end {
+                    let x = (range.start + range.end) / 2;
+                    if v.at(self.prev_fit_x(x)) < idx {
+                        range.start = x + 1;
+                    } else {
+                        range.end = x;
                     }
-                    x += 1;
                 }
+                range.start
             }
             None => idx,
         }
     }
 
+    pub fn char_x(&self, n: usize) -> usize {
+        let idx = self
+            .string
+            .char_indices()
+            .nth(n)
+            .map_or(self.string.len(), |(idx, _)| idx);
+        self.prev_fit_x(str_width(0, &self.string[..idx]))
+    }
+
     #[inline]
     pub fn last_x(&self) -> usize {
         match self.x_to_idx.as_ref() {
@@ -148,6 +227,48 @@ impl Row {
         Some(x)
     }
 
+    pub fn wrap_xs(&self, width: usize) -> Ref<'_, [usize]> {
+        if !matches!(&*self.wrap_xs.borrow(), Some((w, _)) if *w == width) {
+            *self.wrap_xs.borrow_mut() = Some((width, self.find_wrap_xs(width)));
+        }
+        Ref::map(
+            self.wrap_xs.borrow(),
+            |cache| &cache.as_ref().unwrap().1[..],
+        )
+    }
+
+    fn find_wrap_xs(&self, width: usize) -> Vec<usize> {
+        let mut xs = vec![0];
+        let mut start = 0;
+        let mut word_start = None;
+        let mut x = 0;
+
+        while let Some(next_x) = self.next_x(x) {
+            if next_x - start > width {
+                let brk = match word_start {
+                    Some(word_x) if word_x > start => word_x,
+                    _ if x > start => x,
+                    _ => next_x,
+                };
+                xs.push(brk);
+                start = brk;
+                word_start = None;
+                if brk <= x {
+                    continue;
+                }
+            }
+            if matches!(self.char_at(x), ' ' | '\t') {
+                word_start = Some(next_x);
+            }
+            x = next_x;
+        }
+
+        if xs.len() > 1 && xs[xs.len() - 1] == self.last_x() {
+            xs.pop();
+        }
+        xs
+    }
+
     #[inline]
     fn is_char_boundary(&self, x: usize) -> bool {
         match self.x_to_idx.as_ref() {
@@ -176,9 +297,7 @@ impl Row {
 
     pub fn clear(&mut self) {
         self.string.clear();
-        if self.x_to_idx.is_some() {
-            self.update_mappings();
-        }
+        self.update_after_removal();
     }
 
     pub fn read(&self, x1: usize, x2: usize) -> String {
@@ -199,7 +318,13 @@ impl Row {
         self.string
             .chars()
             .take_while(|&ch| ch.is_ascii_whitespace())
-            .fold(0, |w, ch| w + char_width(w, ch))
+            .fold(0, |w, ch| {
+                w + if ch == '\t' {
+                    TAB_WIDTH - w % TAB_WIDTH
+                } else {
+                    1
+                }
+            })
     }
 
     pub fn indent(&mut self, string: &str) -> String {
@@ -217,10 +342,13 @@ impl Row {
     }
 
     pub fn insert_str(&mut self, x: usize, string: &str) -> usize {
-        let idx = self.x_to_idx(x);
-        self.string.insert_str(idx, string);
+        let idx = self.x_to_idx(x) + string.len();
+        self.string.insert_str(idx - string.len(), string);
         self.update_mappings();
-        x + str_width(x, string)
+        // the inserted string may join a cluster, e.g. a combining mark
+        (x..=self.last_x())
+            .find(|&x| self.is_char_boundary(x) && self.x_to_idx(x) >= idx)
+            .unwrap_or_else(|| self.last_x())
     }
 
     pub fn remove_str(&mut self, x1: usize, x2: usize) -> String {
@@ -229,42 +357,53 @@ impl Row {
         let string = self.string.split_off(idx2);
         let removed = self.string.split_off(idx1);
         self.string.push_str(&string);
-        if self.x_to_idx.is_some() {
-            self.update_mappings();
-        }
+        self.update_after_removal();
         removed
     }
 
     pub fn truncate(&mut self, x: usize) {
         let idx = self.x_to_idx(x);
         self.string.truncate(idx);
-        if self.x_to_idx.is_some() {
-            self.update_mappings();
-        }
+        self.update_after_removal();
     }
 
     pub fn split_off(&mut self, x: usize) -> String {
         let idx = self.x_to_idx(x);
         let string = self.string.split_off(idx);
+        self.update_after_removal();
+        string
+    }
+
+    // removing text from printable ASCII leaves printable ASCII
+    fn update_after_removal(&mut self) {
         if self.x_to_idx.is_some() {
             self.update_mappings();
+        } else {
+            *self.wrap_xs.get_mut() = None;
         }
-        string
     }
 
-    fn update_mappings(&mut self) {
+    pub fn update_mappings(&mut self) {
+        *self.wrap_xs.get_mut() = None;
+
+        // printable ASCII is one column per byte, which needs no mappings
+        if self.string.bytes().all(|b| (b' '..=b'~').contains(&b)) {
+            self.x_to_idx = None;
+            return;
+        }
+
         let x_to_idx = self.x_to_idx.get_or_insert(Box::new(UintVec::new()));
         let mut need_mappings = false;
 
         x_to_idx.clear();
 
-        for (idx, ch) in self.string.char_indices() {
-            let width = char_width(x_to_idx.len(), ch);
+        for (idx, cluster) in self.string.grapheme_indices(true) {
+            let width = cluster_width(x_to_idx.len(), cluster);
 
             for i in 0..width {
                 x_to_idx.push(if i == 0 { idx } else { TOMBSTONE });
             }
-            if ch == '\t' || !ch.is_ascii() {
+            if width != 1 || cluster.len() != 1 {
                 need_mappings = true;
             }
         }
@@ -276,50 +415,86 @@ impl Row {
         }
     }
 
-    pub fn draw(&self, canvas: &mut Canvas, x_range: Range<usize>) -> io::Result<()> {
+    pub fn draw(
+        &self,
+        canvas: &mut Canvas,
+        x_range: Range<usize>,
+        overlays: &[Overlay],
+    ) -> io::Result<()> {
         let start_x = self.next_fit_x(x_range.start);
         let end_x = self.prev_fit_x(x_range.end);
         let start = self.x_to_idx(start_x);
         let end = self.x_to_idx(end_x);
 
         if x_range.start < start_x {
-            canvas.set_bg_color(self.faces[start - 1].1)?;
+            canvas.set_bg_color(Overlay::merge(overlays, x_range.start, Fg::Default).1)?;
             canvas.write_repeat(b" ", start_x - x_range.start)?;
         }
 
         let mut x = start_x;
 
-        for (idx, ch) in self.string[start..end].char_indices() {
+        for (idx, cluster) in self.string[start..end].grapheme_indices(true) {
             let idx = start + idx;
-            let width = char_width(x, ch);
-            let (fg, bg) = self.faces[idx];
+            let width = cluster_width(x, cluster);
+            let (fg, bg) = Overlay::merge(overlays, x, self.faces.get(idx));
 
             canvas.set_fg_color(fg)?;
             canvas.set_bg_color(bg)?;
 
-            match ch {
-                '\t' => {
-                    canvas.write_repeat(b" ", width)?;
-                }
-                '\u{200d}' => {
-                    canvas.write(b"\x1b[4m")?;
-                    canvas.write_repeat(b" ", width)?;
-                    canvas.write(b"\x1b[24m")?;
-                }
-                _ => {
-                    let s = &self.string[idx..(idx + ch.len_utf8())];
-                    canvas.write(s.as_bytes())?;
-                }
-            };
+            if cluster == "\t" {
+                canvas.write_repeat(b" ", width)?;
+            } else if let Some(glyph) = escape_glyph(cluster) {
+                canvas.set_fg_color(Fg::Escape)?;
+                canvas.write(glyph.as_bytes())?;
+            } else {
+                canvas.write(cluster.as_bytes())?;
+            }
 
             x += width;
         }
 
         if end_x < x_range.end && x_range.end <= self.last_x() {
-            canvas.set_bg_color(self.faces[end].1)?;
+            canvas.set_bg_color(Overlay::merge(overlays, end_x, Fg::Default).1)?;
             canvas.write_repeat(b" ", x_range.end - end_x)?;
         }
 
-        canvas.set_bg_color(self.trailing_bg)
+        canvas.set_bg_color(Overlay::merge(overlays, self.last_x(), Fg::Default).1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_xs_follow_edits() {
+        let mut row = Row::new("aaaa bbbb cccc".to_string());
+        assert_eq!(&*row.wrap_xs(6), &[0, 5, 10]);
+        assert_eq!(&*row.wrap_xs(10), &[0, 10]);
+
+        row.remove_str(0, 5);
+        assert_eq!(&*row.wrap_xs(10), &[0]);
+        row.insert_str(0, "\u{3042}\u{3042}\u{3042} ");
+        assert_eq!(&*row.wrap_xs(10), &[0, 7]);
+    }
+
+    // maps columns all along a long line, e.g. minified JSON, which takes
+    // minutes rather than a moment if a lookup is linear in the line; run
+    // with `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn long_line_columns() {
+        let string: String = (0..100_000)
+            .map(|i| format!("{{\"id\":{},\"name\":\"caf\u{e9}\t{}\"}},", i, i))
+            .collect();
+        let row = Row::new(string);
+
+        let mut last_x = 0;
+        for (idx, _) in row.string.match_indices("caf\u{e9}") {
+            let x = row.idx_to_x(idx);
+            assert!(x > last_x);
+            assert_eq!(row.x_to_idx(x), idx);
+            last_x = row.idx_to_x(idx + "caf\u{e9}".len());
+        }
     }
 }
@@ -1,11 +1,686 @@
-use std::io::{self, Write};
-use std::ops::Range;
+use std::cell::OnceCell;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{Index, IndexMut, Range};
+use std::os::unix::fs::FileExt;
+use std::rc::Rc;
+use std::slice;
 
 use crate::canvas::Canvas;
-use crate::coord::Pos;
+use crate::coord::{Pos, Size};
+use crate::face::{Fg, Overlay};
+use crate::gutter::Gutter;
 use crate::row::Row;
 
-pub type Rows = Vec<Row>;
+// the number of rows in a leaf or children of an inner node
+const MAX_WIDTH: usize = 64;
+const MIN_WIDTH: usize = MAX_WIDTH / 4;
+
+// a B-tree of rows indexed by line number, so that inserting or
+// removing a line is O(log n) however long the file is
+pub struct Rows {
+    root: Node,
+    source: Option<Rc<Source>>,
+}
+
+enum Node {
+    Leaf(Vec<Row>),
+    Lazy(Lazy),
+    // the total number of rows and the children
+    Inner(usize, Vec<Node>),
+}
+
+// the file that lazy leaves are read from
+struct Source {
+    file: File,
+    // whether lines end with CR LF, judging by the first one
+    crlf: bool,
+    // the first failure to read a leaf, after which saving would lose rows
+    error: OnceCell<io::Error>,
+}
+
+// a leaf read from the file on first access, so that the tree works as a
+// piece table: unread leaves are pieces of the file and the others edits
+struct Lazy {
+    source: Rc<Source>,
+    // the lines including their line endings
+    range: Range<u64>,
+    len: usize,
+    rows: OnceCell<Vec<Row>>,
+}
+
+impl Rows {
+    pub fn new() -> Self {
+        Self {
+            root: Node::Leaf(Vec::new()),
+            source: None,
+        }
+    }
+
+    // only indexes the lines, which are read when accessed
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let mut file = file;
+        let mut ranges = Vec::new();
+        let mut buf = vec![0; 1 << 16];
+        let (mut start, mut pos, mut len) = (0, 0, 0);
+        let mut crlf = None;
+        let mut prev = 0;
+
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            for &b in &buf[..n] {
+                pos += 1;
+                if b == b'\n' {
+                    crlf.get_or_insert(prev == b'\r');
+                    len += 1;
+                    if len == MAX_WIDTH / 2 {
+                        ranges.push((start..pos, len));
+                        start = pos;
+                        len = 0;
+                    }
+                }
+                prev = b;
+            }
+        }
+
+        let source = Rc::new(Source {
+            file,
+            crlf: crlf.unwrap_or(false),
+            error: OnceCell::new(),
+        });
+
+        // the last line, which is empty if the file ends with LF, and the
+        // lines before it if too few for a leaf
+        ranges.push((start..pos, len + 1));
+        if ranges.len() > 1 && len + 1 < MIN_WIDTH {
+            let (range, len) = ranges.pop().unwrap();
+            let last = ranges.last_mut().unwrap();
+            last.0.end = range.end;
+            last.1 += len;
+        }
+
+        let nodes = ranges
+            .into_iter()
+            .map(|(range, len)| Node::Lazy(Lazy::new(&source, range, len)))
+            .collect();
+        let mut rows = Self::from_nodes(nodes);
+        rows.source = Some(source);
+        Ok(rows)
+    }
+
+    // builds the tree bottom up with half full nodes
+    fn from_nodes(nodes: Vec<Node>) -> Self {
+        let mut nodes = nodes;
+
+        while nodes.len() > 1 {
+            nodes = half_full(nodes)
+                .into_iter()
+                .map(|children| Node::Inner(children.iter().map(Node::len).sum(), children))
+                .collect();
+        }
+
+        Self {
+            root: nodes.pop().unwrap_or_else(|| Node::Leaf(Vec::new())),
+            source: None,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+
+    // whether rows are read from the file on demand
+    #[inline]
+    pub fn is_lazy(&self) -> bool {
+        self.source.is_some()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn push(&mut self, row: Row) {
+        self.insert(self.len(), row);
+    }
+
+    pub fn insert(&mut self, y: usize, row: Row) {
+        assert!(y <= self.len(), "row index out of bounds");
+
+        if let Some(right) = self.root.insert(y, row) {
+            let left = mem::replace(&mut self.root, Node::Leaf(Vec::new()));
+            self.root = Node::Inner(left.len() + right.len(), vec![left, right]);
+        }
+    }
+
+    pub fn remove(&mut self, y: usize) -> Row {
+        assert!(y < self.len(), "row index out of bounds");
+
+        let row = self.root.remove(y);
+        while let Node::Inner(_, children) = &mut self.root {
+            if children.len() > 1 {
+                break;
+            }
+            self.root = children.pop().unwrap();
+        }
+        row
+    }
+
+    // joins the rows with the line ending of the file, copying unread ones
+    // from it as is, and fails if any could not be read
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if let Some(e) = self.source.as_ref().and_then(|source| source.error.get()) {
+            return Err(io::Error::new(e.kind(), e.to_string()));
+        }
+        self.root.write_to(writer, self.newline(), &mut false)
+    }
+
+    // the first failure to read a leaf from the file
+    pub fn error(&self) -> Option<&io::Error> {
+        self.source.as_ref()?.error.get()
+    }
+
+    fn newline(&self) -> &'static [u8] {
+        match self.source.as_deref() {
+            Some(source) if source.crlf => b"\r\n",
+            _ => b"\n",
+        }
+    }
+
+    // the offset of row y in the file as saved, taking the lengths of
+    // unread leaves from the file
+    pub fn byte_offset(&self, y: usize) -> usize {
+        self.root.byte_offset(y, self.newline().len())
+    }
+
+    // the row of the nth character and its index in the row, counting a
+    // line ending as one character; unread leaves are only counted
+    pub fn find_char(&self, n: usize) -> Option<(usize, usize)> {
+        self.root.find_char(n).ok()
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_from(0)
+    }
+
+    // iterates over the rows from y to the end
+    pub fn iter_from(&self, y: usize) -> Iter<'_> {
+        let mut stack = Vec::new();
+        let mut node = &self.root;
+        let mut y = y;
+
+        loop {
+            let rows = match node {
+                Node::Leaf(rows) => rows,
+                Node::Lazy(lazy) => lazy.rows(),
+                Node::Inner(_, children) => {
+                    let (i, child_y) = child_index(children, y, true);
+                    stack.push(children[(i + 1)..].iter());
+                    node = &children[i];
+                    y = child_y;
+                    continue;
+                }
+            };
+            let rows = rows[cmp::min(y, rows.len())..].iter();
+            return Iter { stack, rows };
+        }
+    }
+
+    pub fn iter_mut_from(&mut self, y: usize) -> IterMut<'_> {
+        let mut stack = Vec::new();
+        let mut node = &mut self.root;
+        let mut y = y;
+
+        loop {
+            let rows = match node {
+                Node::Leaf(rows) => rows,
+                Node::Lazy(lazy) => lazy.rows_mut(),
+                Node::Inner(_, children) => {
+                    let (i, child_y) = child_index(children, y, true);
+                    let (left, right) = children.split_at_mut(i + 1);
+                    stack.push(right.iter_mut());
+                    node = &mut left[i];
+                    y = child_y;
+                    continue;
+                }
+            };
+            let len = rows.len();
+            let rows = rows[cmp::min(y, len)..].iter_mut();
+            return IterMut {
+                stack,
+                rows,
+                loaded_only: false,
+            };
+        }
+    }
+
+    // skips rows not read from the file yet
+    pub fn iter_loaded_mut(&mut self) -> IterMut<'_> {
+        IterMut {
+            stack: vec![slice::from_mut(&mut self.root).iter_mut()],
+            rows: [].iter_mut(),
+            loaded_only: true,
+        }
+    }
+}
+
+impl Node {
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            Self::Leaf(rows) => rows.len(),
+            Self::Lazy(lazy) => lazy.len,
+            Self::Inner(len, _) => *len,
+        }
+    }
+
+    #[inline]
+    fn width(&self) -> usize {
+        match self {
+            Self::Leaf(rows) => rows.len(),
+            Self::Lazy(lazy) => lazy.len,
+            Self::Inner(_, children) => children.len(),
+        }
+    }
+
+    fn get(&self, y: usize) -> &Row {
+        match self {
+            Self::Leaf(rows) => &rows[y],
+            Self::Lazy(lazy) => &lazy.rows()[y],
+            Self::Inner(_, children) => {
+                let (i, y) = child_index(children, y, false);
+                children[i].get(y)
+            }
+        }
+    }
+
+    fn get_mut(&mut self, y: usize) -> &mut Row {
+        match self {
+            Self::Leaf(rows) => &mut rows[y],
+            Self::Lazy(lazy) => &mut lazy.rows_mut()[y],
+            Self::Inner(_, children) => {
+                let (i, y) = child_index(children, y, false);
+                children[i].get_mut(y)
+            }
+        }
+    }
+
+    fn byte_offset(&self, y: usize, newline_len: usize) -> usize {
+        let rows = match self {
+            _ if y == 0 => return 0,
+            Self::Leaf(rows) => rows,
+            Self::Lazy(lazy) if y == lazy.len && !lazy.is_loaded() => {
+                return (lazy.range.end - lazy.range.start) as usize;
+            }
+            Self::Lazy(lazy) => lazy.rows(),
+            Self::Inner(_, children) => {
+                let (i, y) = child_index(children, y, true);
+                return children[..i]
+                    .iter()
+                    .map(|child| child.byte_offset(child.len(), newline_len))
+                    .sum::<usize>()
+                    + children[i].byte_offset(y, newline_len);
+            }
+        };
+        rows[..y]
+            .iter()
+            .map(|row| row.string.len() + newline_len)
+            .sum()
+    }
+
+    // the characters left after the node if the nth is not in it
+    fn find_char(&self, n: usize) -> Result<(usize, usize), usize> {
+        let rows = match self {
+            Self::Leaf(rows) => rows,
+            Self::Lazy(lazy) if !lazy.is_loaded() => match lazy.char_count() {
+                Some(count) if n >= count => return Err(n - count),
+                _ => lazy.rows(),
+            },
+            Self::Lazy(lazy) => lazy.rows(),
+            Self::Inner(_, children) => {
+                let mut n = n;
+                let mut start = 0;
+                for child in children {
+                    match child.find_char(n) {
+                        Ok((y, i)) => return Ok((start + y, i)),
+                        Err(rest) => n = rest,
+                    }
+                    start += child.len();
+                }
+                return Err(n);
+            }
+        };
+
+        let mut n = n;
+        for (y, row) in rows.iter().enumerate() {
+            let len = row.string.chars().count();
+            if n <= len {
+                return Ok((y, n));
+            }
+            n -= len + 1;
+        }
+        Err(n)
+    }
+
+    // a leaf whose length can change
+    fn make_leaf(&mut self) {
+        if let Self::Lazy(lazy) = self {
+            *self = Self::Leaf(mem::take(lazy.rows_mut()));
+        }
+    }
+
+    // returns the right half if the node overflows
+    fn insert(&mut self, y: usize, row: Row) -> Option<Self> {
+        self.make_leaf();
+
+        match self {
+            Self::Leaf(rows) => rows.insert(y, row),
+            Self::Lazy(_) => unreachable!(),
+            Self::Inner(len, children) => {
+                let (i, y) = child_index(children, y, true);
+                if let Some(right) = children[i].insert(y, row) {
+                    children.insert(i + 1, right);
+                }
+                *len += 1;
+            }
+        }
+
+        if self.width() > MAX_WIDTH {
+            Some(self.split())
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, y: usize) -> Row {
+        self.make_leaf();
+
+        match self {
+            Self::Leaf(rows) => rows.remove(y),
+            Self::Lazy(_) => unreachable!(),
+            Self::Inner(len, children) => {
+                let (i, y) = child_index(children, y, false);
+                let row = children[i].remove(y);
+                if children[i].width() < MIN_WIDTH && children.len() > 1 {
+                    merge_child(children, i);
+                }
+                *len -= 1;
+                row
+            }
+        }
+    }
+
+    fn split(&mut self) -> Self {
+        match self {
+            Self::Leaf(rows) => Self::Leaf(rows.split_off(rows.len() / 2)),
+            Self::Lazy(_) => unreachable!(),
+            Self::Inner(len, children) => {
+                let right = children.split_off(children.len() / 2);
+                let right_len = right.iter().map(Self::len).sum();
+                *len -= right_len;
+                Self::Inner(right_len, right)
+            }
+        }
+    }
+
+    fn append(&mut self, other: Self) {
+        let mut other = other;
+        self.make_leaf();
+        other.make_leaf();
+
+        match (self, other) {
+            (Self::Leaf(rows), Self::Leaf(other_rows)) => rows.extend(other_rows),
+            (Self::Inner(len, children), Self::Inner(other_len, other_children)) => {
+                *len += other_len;
+                children.extend(other_children);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // need_newline is set after a row that is not followed by its line
+    // ending yet, unlike the last line of an unread leaf
+    fn write_to<W: Write>(
+        &self,
+        writer: &mut W,
+        newline: &[u8],
+        need_newline: &mut bool,
+    ) -> io::Result<()> {
+        let rows = match self {
+            Self::Leaf(rows) => rows,
+            Self::Lazy(lazy) if lazy.is_loaded() => lazy.rows(),
+            Self::Lazy(lazy) => {
+                let buf = lazy.read()?;
+                if *need_newline {
+                    writer.write_all(newline)?;
+                }
+                *need_newline = !buf.ends_with(b"\n");
+                return writer.write_all(&buf);
+            }
+            Self::Inner(_, children) => {
+                for child in children {
+                    child.write_to(writer, newline, need_newline)?;
+                }
+                return Ok(());
+            }
+        };
+
+        for row in rows {
+            if mem::replace(need_newline, true) {
+                writer.write_all(newline)?;
+            }
+            writer.write_all(row.string.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Lazy {
+    fn new(source: &Rc<Source>, range: Range<u64>, len: usize) -> Self {
+        Self {
+            source: Rc::clone(source),
+            range,
+            len,
+            rows: OnceCell::new(),
+        }
+    }
+
+    #[inline]
+    fn is_loaded(&self) -> bool {
+        self.rows.get().is_some()
+    }
+
+    fn read(&self) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; (self.range.end - self.range.start) as usize];
+        // a short read means that the file was truncated
+        self.source
+            .file
+            .read_exact_at(&mut buf, self.range.start)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => changed_error(),
+                _ => e,
+            })?;
+        Ok(buf)
+    }
+
+    // the characters in the leaf without reading it into rows, or None if
+    // it cannot be read
+    fn char_count(&self) -> Option<usize> {
+        let buf = self.read().ok()?;
+        let chars = buf.iter().filter(|&&b| b & 0xc0 != 0x80).count();
+        let crs = if self.source.crlf {
+            buf.windows(2).filter(|w| w == b"\r\n").count()
+        } else {
+            0
+        };
+        Some(chars - crs)
+    }
+
+    fn load(&self) -> io::Result<Vec<Row>> {
+        let string = String::from_utf8(self.read()?)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8 in file"))?;
+
+        // the last leaf has no line ending after its last line
+        let newlines = string.matches('\n').count();
+        if newlines != self.len && newlines + 1 != self.len {
+            return Err(changed_error());
+        }
+
+        Ok(string
+            .split('\n')
+            .take(self.len)
+            .map(|s| match s.strip_suffix('\r') {
+                Some(s) if self.source.crlf => Row::new(s.to_string()),
+                _ => Row::new(s.to_string()),
+            })
+            .collect())
+    }
+
+    // rows that cannot be read are left empty, and the buffer turns
+    // read-only and saving fails, see Rows::error
+    fn rows(&self) -> &Vec<Row> {
+        self.rows.get_or_init(|| {
+            self.load().unwrap_or_else(|e| {
+                let _ = self.source.error.set(e);
+                (0..self.len).map(|_| Row::new(String::new())).collect()
+            })
+        })
+    }
+
+    fn rows_mut(&mut self) -> &mut Vec<Row> {
+        self.rows();
+        self.rows.get_mut().unwrap()
+    }
+}
+
+fn changed_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "file changed since it was opened",
+    )
+}
+
+// finds the child containing y, or the last one if y is the end and
+// end_ok is set, and the index of y in it
+fn child_index(children: &[Node], y: usize, end_ok: bool) -> (usize, usize) {
+    let mut y = y;
+    for (i, child) in children.iter().enumerate() {
+        if y < child.len() || (end_ok && i == children.len() - 1) {
+            return (i, y);
+        }
+        y -= child.len();
+    }
+    panic!("row index out of bounds");
+}
+
+// splits items into half full nodes, the last one taking the rest if it
+// would underflow
+fn half_full<T>(items: Vec<T>) -> Vec<Vec<T>> {
+    let mut iter = items.into_iter().peekable();
+    let mut groups: Vec<Vec<T>> = Vec::new();
+
+    while iter.peek().is_some() {
+        groups.push(iter.by_ref().take(MAX_WIDTH / 2).collect());
+    }
+    if groups.len() > 1 && groups[groups.len() - 1].len() < MIN_WIDTH {
+        let last = groups.pop().unwrap();
+        groups.last_mut().unwrap().extend(last);
+    }
+    groups
+}
+
+// merges an underflowing child with a sibling, splitting them again if
+// they do not fit in one node
+fn merge_child(children: &mut Vec<Node>, i: usize) {
+    let i = if i + 1 < children.len() { i } else { i - 1 };
+    let right = children.remove(i + 1);
+    children[i].append(right);
+    if children[i].width() > MAX_WIDTH {
+        let right = children[i].split();
+        children.insert(i + 1, right);
+    }
+}
+
+impl Index<usize> for Rows {
+    type Output = Row;
+
+    #[inline]
+    fn index(&self, y: usize) -> &Row {
+        self.root.get(y)
+    }
+}
+
+impl IndexMut<usize> for Rows {
+    #[inline]
+    fn index_mut(&mut self, y: usize) -> &mut Row {
+        self.root.get_mut(y)
+    }
+}
+
+impl FromIterator<Row> for Rows {
+    fn from_iter<I: IntoIterator<Item = Row>>(iter: I) -> Self {
+        let rows = iter.into_iter().collect();
+        Self::from_nodes(half_full(rows).into_iter().map(Node::Leaf).collect())
+    }
+}
+
+pub struct Iter<'a> {
+    stack: Vec<slice::Iter<'a, Node>>,
+    rows: slice::Iter<'a, Row>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(row);
+            }
+            match self.stack.last_mut()?.next() {
+                Some(Node::Leaf(rows)) => self.rows = rows.iter(),
+                Some(Node::Lazy(lazy)) => self.rows = lazy.rows().iter(),
+                Some(Node::Inner(_, children)) => self.stack.push(children.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+pub struct IterMut<'a> {
+    stack: Vec<slice::IterMut<'a, Node>>,
+    rows: slice::IterMut<'a, Row>,
+    loaded_only: bool,
+}
+
+impl<'a> Iterator for IterMut<'a> {
+    type Item = &'a mut Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(row);
+            }
+            match self.stack.last_mut()?.next() {
+                Some(Node::Leaf(rows)) => self.rows = rows.iter_mut(),
+                Some(Node::Lazy(lazy)) if self.loaded_only && !lazy.is_loaded() => (),
+                Some(Node::Lazy(lazy)) => self.rows = lazy.rows_mut().iter_mut(),
+                Some(Node::Inner(_, children)) => self.stack.push(children.iter_mut()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
 
 pub trait RowsMethods {
     fn last_pos(&self) -> Pos;
@@ -21,6 +696,16 @@ pub trait RowsMethods {
         canvas: &mut Canvas,
         x_range: Range<usize>,
         y_range: Range<usize>,
+        gutter: &Gutter,
+        overlays: &dyn Fn(usize) -> Vec<Overlay>,
+    ) -> io::Result<()>;
+    fn draw_wrapped(
+        &self,
+        canvas: &mut Canvas,
+        offset: Pos,
+        size: Size,
+        gutter: &Gutter,
+        overlays: &dyn Fn(usize) -> Vec<Overlay>,
     ) -> io::Result<()>;
 }
 
@@ -93,12 +778,13 @@ impl RowsMethods for Rows {
             Pos::new(x, pos.y)
         } else {
             let string = self[pos.y].split_off(pos.x);
-            let mut rows = self.split_off(pos.y + 1);
             self[pos.y].push_str(strings[0]);
-            self.append(&mut strings[1..].iter().map(|&s| Row::new(s.into())).collect());
-            let pos = self.last_pos();
+            for (i, &s) in strings[1..].iter().enumerate() {
+                self.insert(pos.y + 1 + i, Row::new(s.into()));
+            }
+            let y = pos.y + strings.len() - 1;
+            let pos = Pos::new(self[y].last_x(), y);
             self[pos.y].push_str(&string);
-            self.append(&mut rows);
             pos
         }
     }
@@ -109,16 +795,10 @@ impl RowsMethods for Rows {
         } else {
             let mut removed = vec![self[pos1.y].split_off(pos1.x)];
             let string = self[pos2.y].split_off(pos2.x);
-            let mut rows = self.split_off(pos2.y + 1);
             self[pos1.y].push_str(&string);
-            removed.append(
-                &mut self
-                    .split_off(pos1.y + 1)
-                    .into_iter()
-                    .map(|row| row.string)
-                    .collect(),
-            );
-            self.append(&mut rows);
+            for _ in pos1.y..pos2.y {
+                removed.push(self.remove(pos1.y + 1).string);
+            }
             removed.join("\n")
         }
     }
@@ -128,14 +808,343 @@ impl RowsMethods for Rows {
         canvas: &mut Canvas,
         x_range: Range<usize>,
         y_range: Range<usize>,
+        gutter: &Gutter,
+        overlays: &dyn Fn(usize) -> Vec<Overlay>,
     ) -> io::Result<()> {
         for y in y_range {
+            gutter.draw(canvas, y, self.len())?;
             if y < self.len() {
-                self[y].draw(canvas, x_range.clone())?;
+                self[y].draw(canvas, x_range.clone(), &overlays(y))?;
+            }
+            canvas.write(b"\x1b[K")?;
+            canvas.write(b"\r\n")?;
+        }
+        Ok(())
+    }
+
+    fn draw_wrapped(
+        &self,
+        canvas: &mut Canvas,
+        offset: Pos,
+        size: Size,
+        gutter: &Gutter,
+        overlays: &dyn Fn(usize) -> Vec<Overlay>,
+    ) -> io::Result<()> {
+        let mut n = 0;
+
+        for (y, row) in (offset.y..).zip(self.iter_from(offset.y)) {
+            let xs = row.wrap_xs(size.w);
+            let overlays = overlays(y);
+
+            for (i, &x) in xs.iter().enumerate() {
+                if n == size.h {
+                    return Ok(());
+                }
+                if y == offset.y && matches!(xs.get(i + 1), Some(&next_x) if next_x <= offset.x) {
+                    continue;
+                }
+
+                if i == 0 {
+                    gutter.draw(canvas, y, self.len())?;
+                } else {
+                    gutter.draw_blank(canvas)?;
+                }
+
+                if let Some(&next_x) = xs.get(i + 1) {
+                    row.draw(canvas, x..next_x, &overlays)?;
+                    canvas.write_repeat(b" ", size.w - (next_x - x).min(size.w))?;
+                    canvas.set_fg_color(Fg::LineNumber)?;
+                    canvas.write(b"\\")?;
+                } else {
+                    row.draw(canvas, x..(x + size.w), &overlays)?;
+                }
+
+                canvas.write(b"\x1b[K")?;
+                canvas.write(b"\r\n")?;
+                n += 1;
             }
+        }
+
+        while n < size.h {
+            gutter.draw_blank(canvas)?;
             canvas.write(b"\x1b[K")?;
             canvas.write(b"\r\n")?;
+            n += 1;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("ele-rows-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn lines(n: usize, newline: &str, ends_with_newline: bool) -> String {
+        let mut string: String = (0..n).map(|i| format!("line {}{}", i, newline)).collect();
+        if !ends_with_newline {
+            string.push_str("last");
+        }
+        string
+    }
+
+    fn write_to_vec(rows: &Rows) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        rows.write_to(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn loaded_leaves(node: &Node) -> usize {
+        match node {
+            Node::Leaf(_) => 1,
+            Node::Lazy(lazy) => lazy.is_loaded() as usize,
+            Node::Inner(_, children) => children.iter().map(loaded_leaves).sum(),
+        }
+    }
+
+    #[test]
+    fn offsets_without_reading_rows() {
+        let contents: String = (0..200).map(|i| format!("l\u{e9}ne {}\r\n", i)).collect();
+        let path = temp_file("offsets", contents.as_bytes());
+        let rows = Rows::from_file(File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let line = |y: usize| format!("l\u{e9}ne {}\r\n", y);
+        let bytes: usize = (0..150).map(|y| line(y).len()).sum();
+        let chars: usize = (0..150).map(|y| line(y).chars().count() - 1).sum();
+
+        // row 128 starts a leaf, so no leaf is read
+        let start: usize = (0..128).map(|y| line(y).len()).sum();
+        assert_eq!(rows.byte_offset(128), start);
+        assert_eq!(loaded_leaves(&rows.root), 0);
+
+        assert_eq!(rows.byte_offset(150), bytes);
+        assert_eq!(rows.find_char(chars + 3), Some((150, 3)));
+        assert_eq!(rows.find_char(chars - 1), Some((149, 8)));
+        assert_eq!(rows.find_char(contents.len()), None);
+        assert_eq!(loaded_leaves(&rows.root), 1);
+    }
+
+    #[test]
+    fn from_file_round_trip() {
+        for &n in &[0, 1, 31, 32, 33, 64, 100] {
+            for &newline in &["\n", "\r\n"] {
+                for &ends_with_newline in &[true, false] {
+                    let contents = lines(n, newline, ends_with_newline);
+                    let path = temp_file("round-trip", contents.as_bytes());
+                    let name = format!("{} lines {:?} {}", n, newline, ends_with_newline);
+
+                    let rows = Rows::from_file(File::open(&path).unwrap()).unwrap();
+                    assert_eq!(rows.len(), n + 1, "{}", name);
+                    assert_eq!(
+                        write_to_vec(&rows).unwrap(),
+                        contents.as_bytes(),
+                        "{}",
+                        name
+                    );
+
+                    // reading some leaves mixes copied and joined rows
+                    for y in (0..rows.len()).step_by(40) {
+                        assert!(!rows[y].string.ends_with('\r'), "{}", name);
+                    }
+                    assert_eq!(rows[rows.len() - 1].string.is_empty(), ends_with_newline);
+                    assert_eq!(
+                        write_to_vec(&rows).unwrap(),
+                        contents.as_bytes(),
+                        "{}",
+                        name
+                    );
+
+                    fs::remove_file(&path).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edited_rows_keep_line_endings() {
+        let contents = lines(100, "\r\n", true);
+        let path = temp_file("edited", contents.as_bytes());
+        let mut rows = Rows::from_file(File::open(&path).unwrap()).unwrap();
+
+        rows.insert(40, Row::new("new".to_string()));
+        rows.remove(70);
+
+        let mut expected: Vec<_> = contents.split("\r\n").map(str::to_string).collect();
+        expected.insert(40, "new".to_string());
+        expected.remove(70);
+        assert_eq!(
+            write_to_vec(&rows).unwrap(),
+            expected.join("\r\n").as_bytes()
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unreadable_leaf_fails_save() {
+        let path = temp_file("truncated", lines(100, "\n", true).as_bytes());
+        let rows = Rows::from_file(File::open(&path).unwrap()).unwrap();
+
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(10)
+            .unwrap();
+        assert_eq!(rows[50].string, "");
+        assert!(write_to_vec(&rows).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_utf8_fails_save() {
+        let mut contents = lines(100, "\n", true).into_bytes();
+        let idx = contents.windows(7).position(|w| w == b"line 40").unwrap();
+        contents[idx] = 0xff;
+        let path = temp_file("invalid-utf8", &contents);
+
+        // copied as is while unread
+        let rows = Rows::from_file(File::open(&path).unwrap()).unwrap();
+        assert_eq!(write_to_vec(&rows).unwrap(), contents);
+
+        let _ = &rows[40];
+        let e = write_to_vec(&rows).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    // checks the fill of every node and the cached lengths, returning the
+    // length and height of the node
+    fn check(node: &Node, is_root: bool) -> (usize, usize) {
+        if !is_root {
+            assert!(node.width() >= MIN_WIDTH, "underflow: {}", node.width());
+        }
+        match node {
+            Node::Leaf(rows) => {
+                assert!(rows.len() <= MAX_WIDTH, "overflow: {}", rows.len());
+                (rows.len(), 0)
+            }
+            Node::Lazy(lazy) => (lazy.len, 0),
+            Node::Inner(len, children) => {
+                assert!(children.len() <= MAX_WIDTH, "overflow: {}", children.len());
+                assert!(!is_root || children.len() > 1, "root with one child");
+                let (sum, height) = children.iter().fold((0, None), |(sum, height), child| {
+                    let (len, h) = check(child, false);
+                    assert_eq!(height.unwrap_or(h), h, "unbalanced");
+                    (sum + len, Some(h))
+                });
+                assert_eq!(*len, sum, "cached length");
+                (sum, height.unwrap() + 1)
+            }
+        }
+    }
+
+    // a linear congruential generator, so that failures can be replayed
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % n
+        }
+    }
+
+    fn assert_same(rows: &Rows, model: &[String], random: &mut Random) {
+        check(&rows.root, true);
+        assert_eq!(rows.len(), model.len());
+        assert!(rows.iter().map(|row| &row.string).eq(model.iter()));
+
+        // around leaf boundaries, which are at multiples of half a node
+        let mut ys: Vec<_> = (0..4).map(|_| random.below(model.len() + 1)).collect();
+        for y in (MAX_WIDTH / 2..model.len()).step_by(MAX_WIDTH / 2).take(4) {
+            ys.extend(&[y - 1, y, y + 1]);
+        }
+        for y in ys.into_iter().filter(|&y| y <= model.len()) {
+            assert!(
+                rows.iter_from(y)
+                    .map(|row| &row.string)
+                    .eq(model[y..].iter()),
+                "{}",
+                y
+            );
+            if y < model.len() {
+                assert_eq!(rows[y].string, model[y]);
+            }
+        }
+    }
+
+    fn edit_randomly(rows: &mut Rows, model: &mut Vec<String>, seed: u64) {
+        let mut random = Random(seed);
+
+        for i in 0..3000 {
+            // grow and shrink in turns, so that nodes both split and merge
+            let grow = (i / 500) % 2 == 0;
+            match random.below(10) {
+                0..=4 if grow || model.len() <= 1 => {
+                    let y = random.below(model.len() + 1);
+                    let string = format!("inserted {}", i);
+                    rows.insert(y, Row::new(string.clone()));
+                    model.insert(y, string);
+                }
+                0..=6 if model.len() > 1 => {
+                    let y = random.below(model.len());
+                    assert_eq!(rows.remove(y).string, model.remove(y));
+                }
+                _ => {
+                    let y = random.below(model.len());
+                    let string = format!("changed {}", i);
+                    for row in rows.iter_mut_from(y).take(2) {
+                        row.string = string.clone();
+                    }
+                    for row in model[y..].iter_mut().take(2) {
+                        *row = string.clone();
+                    }
+                }
+            }
+            if i % 50 == 0 {
+                assert_same(rows, model, &mut random);
+            }
+        }
+        assert_same(rows, model, &mut random);
+    }
+
+    #[test]
+    fn edits_match_vec() {
+        for &(n, seed) in &[(1, 1), (17, 2), (64, 3), (65, 4), (1000, 5), (5000, 6)] {
+            let mut model: Vec<_> = (0..n).map(|i| format!("row {}", i)).collect();
+            let mut rows: Rows = model.iter().cloned().map(Row::new).collect();
+            edit_randomly(&mut rows, &mut model, seed);
+        }
+    }
+
+    #[test]
+    fn lazy_edits_match_vec() {
+        for &(n, seed) in &[(0, 7), (31, 8), (32, 9), (40, 10), (3000, 11)] {
+            let contents = lines(n, "\n", true);
+            let path = temp_file(&format!("lazy-edits-{}", n), contents.as_bytes());
+            let mut rows = Rows::from_file(File::open(&path).unwrap()).unwrap();
+            let mut model: Vec<_> = contents.split('\n').map(str::to_string).collect();
+
+            assert_same(&rows, &model, &mut Random(seed));
+            edit_randomly(&mut rows, &mut model, seed);
+            assert_eq!(write_to_vec(&rows).unwrap(), model.join("\n").as_bytes());
+
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -11,7 +11,9 @@ pub trait Syntax {
     fn fg_color(&self, term: Term) -> &'static [u8];
     fn bg_color(&self, term: Term) -> &'static [u8];
     fn indent_unit(&self) -> Option<&'static str>;
-    fn update_rows(&self, rows: &mut [Row]) -> usize;
+    // highlights rows until one's context is unchanged, returning how many;
+    // the first row has no context if the rows above were not highlighted
+    fn update_rows(&self, rows: &mut dyn Iterator<Item = &mut Row>) -> usize;
 }
 
 impl dyn Syntax {
@@ -1,5 +1,4 @@
 use crate::canvas::Term;
-use crate::face::{Bg, Fg};
 use crate::row::Row;
 use crate::syntax::Syntax;
 
@@ -30,18 +29,18 @@ impl Syntax for Plain {
         None
     }
 
-    fn update_rows(&self, rows: &mut [Row]) -> usize {
-        for (i, row) in rows.iter_mut().enumerate() {
-            if i > 0 && row.context.is_some() {
-                return i;
+    fn update_rows(&self, rows: &mut dyn Iterator<Item = &mut Row>) -> usize {
+        let mut len = 0;
+
+        for row in rows {
+            if len > 0 && row.context.is_some() {
+                break;
             }
             row.context = Some(String::new());
             row.faces.clear();
-            row.faces
-                .resize(row.string.len(), (Fg::Default, Bg::Default));
-            row.trailing_bg = Bg::Default;
+            len += 1;
         }
 
-        rows.len()
+        len
     }
 }
@@ -5,7 +5,7 @@ use std::str::{CharIndices, Chars};
 
 use self::TokenKind::*;
 use crate::canvas::Term;
-use crate::face::{Bg, Fg};
+use crate::face::Fg;
 use crate::row::Row;
 use crate::syntax::Syntax;
 
@@ -36,18 +36,20 @@ impl Syntax for Rust {
         Some("    ")
     }
 
-    fn update_rows(&self, rows: &mut [Row]) -> usize {
+    fn update_rows(&self, rows: &mut dyn Iterator<Item = &mut Row>) -> usize {
         let mut context_v = Vec::new();
         let mut context_s = String::new();
+        let mut len = 0;
 
-        for (i, row) in rows.iter_mut().enumerate() {
-            if i == 0 {
+        for row in rows {
+            if len == 0 {
+                // the rows above were not highlighted, e.g. in a large file
                 if row.context.is_none() {
-                    row.context = Some(String::new());
+                    row.context = Some("\0?".to_string());
                 }
             } else {
                 if row.context.as_ref() == Some(&context_s) {
-                    return i;
+                    break;
                 }
                 let context = row.context.get_or_insert(String::new());
                 context.clear();
@@ -57,9 +59,10 @@ impl Syntax for Rust {
             context_v.clear();
             context_s.clear();
             self.update_row(row, &mut context_v, &mut context_s);
+            len += 1;
         }
 
-        rows.len()
+        len
     }
 }
 
@@ -69,14 +72,21 @@ impl Rust {
         let mut prev_token: Option<Token> = None;
 
         row.faces.clear();
-        row.faces
-            .resize(row.string.len(), (Fg::Default, Bg::Default));
-        row.trailing_bg = Bg::Default;
         row.indent_level = 0;
 
         while let Some(token) = tokens.next() {
+            // a delimiter may close one above the rows highlighted
+            let unmatched = match (token.kind, innermost_open(context_v)) {
+                (CloseBrace | CloseBracket | CloseParen, Some(Unknown)) => false,
+                (CloseBrace | CloseBracket | CloseParen, open) => {
+                    !open.is_some_and(|open| is_pair(open, token.kind))
+                }
+                _ => false,
+            };
+
             // Highlight
             let fg = match token.kind {
+                _ if unmatched => Fg::Error,
                 BlockComment { .. } | LineComment => Fg::Comment,
                 CharLit | RawStrLit { .. } | StrLit { .. } => Fg::String,
                 Const | Fn | For | Keyword | Let | Mod | Mut | Static | Where { .. } => Fg::Keyword,
@@ -107,9 +117,8 @@ impl Rust {
                 _ => Fg::Default,
             };
 
-            for i in token.start..token.end {
-                row.faces[i].0 = fg;
-            }
+            row.faces.push(token.start, fg);
+            row.faces.push(token.end, Fg::Default);
 
             // Indent
             match token.kind {
@@ -196,9 +205,19 @@ impl Rust {
                 _ => (),
             }
 
-            // Derive the context of the next row
+            // Derive the context of the next row, closing any delimiters left
+            // open inside the one closed
+            if unmatched {
+                if let Some(i) = context_v
+                    .iter()
+                    .rposition(|&open| is_pair(open, token.kind))
+                {
+                    context_v.truncate(i + 1);
+                }
+            }
             match token.kind {
-                Expr { .. }
+                Unknown
+                | Expr { .. }
                 | OpenAttribute { .. }
                 | OpenBracket { .. }
                 | OpenParen { .. }
@@ -327,6 +346,9 @@ impl Rust {
                 Where { lf } => {
                     string.push_str(if lf { "\0w\n" } else { "\0w" });
                 }
+                Unknown => {
+                    string.push_str("\0?");
+                }
                 OpenAttribute { lf } => {
                     string.push_str(if lf { "#[\n" } else { "#[" });
                 }
@@ -405,6 +427,8 @@ enum TokenKind {
     Semi,
     Static,
     StrLit { open: bool },
+    // the context of rows highlighted without the rows above
+    Unknown,
     UpperIdent,
     Where { lf: bool },
 }
@@ -426,6 +450,24 @@ impl<'a> Tokens<'a> {
     }
 }
 
+// the innermost open delimiter, skipping expressions and where clauses
+fn innermost_open(context_v: &[TokenKind]) -> Option<TokenKind> {
+    context_v
+        .iter()
+        .rev()
+        .find(|kind| !matches!(kind, Expr { .. } | Where { .. }))
+        .copied()
+}
+
+fn is_pair(open: TokenKind, close: TokenKind) -> bool {
+    matches!(
+        (open, close),
+        (OpenBrace { .. }, CloseBrace)
+            | (OpenBracket { .. } | OpenAttribute { .. }, CloseBracket)
+            | (OpenParen { .. }, CloseParen)
+    )
+}
+
 fn is_delim(ch: char) -> bool {
     ch.is_ascii_whitespace() || ch != '_' && ch.is_ascii_punctuation()
 }
@@ -531,6 +573,7 @@ impl<'a> Iterator for Tokens<'a> {
                 Some((_, 'w')) => Where {
                     lf: self.chars.next_if(|&(_, ch)| ch == '\n').is_some(),
                 },
+                Some((_, '?')) => Unknown,
                 _ => Punct,
             },
 
@@ -715,3 +758,71 @@ impl<'a> Tokens<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the first row has no context when highlighted without the rows above
+    fn errors(text: &str, context: Option<&str>) -> Vec<(usize, usize)> {
+        let mut rows: Vec<Row> = text.lines().map(|s| Row::new(s.to_string())).collect();
+        rows[0].context = context.map(String::from);
+        Rust.update_rows(&mut rows.iter_mut());
+        let mut errors = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            for idx in 0..row.string.len() {
+                if row.faces.get(idx) == Fg::Error {
+                    errors.push((y, idx));
+                }
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn unmatched_delimiters_are_errors() {
+        assert_eq!(
+            errors("fn f() {\n    g(a];\n}\n)", Some("")),
+            vec![(1, 7), (2, 0), (3, 0)]
+        );
+        // the stray parenthesis is flagged, and the block still closes
+        assert_eq!(
+            errors("fn f() {\n    g(a));\n    #[cfg(x)]]\n}", Some("")),
+            vec![(1, 8), (2, 13)]
+        );
+    }
+
+    #[test]
+    fn matched_delimiters_are_not_errors() {
+        let text = r##"#[derive(Clone)]
+struct S<'a> {
+    v: Vec<[u8; 4]>,
+    s: &'a str,
+}
+
+impl<'a> S<'a>
+where
+    Self: Clone,
+{
+    fn f(&self) -> Option<char> {
+        let c = '}'; // ) ]
+        /* ( { [ */
+        let s = "{ ( [";
+        let r = r#"] ) }"#;
+        let g = |x: usize| [x, (x + 1)][0];
+        if let Some(&b) = self.v.get(0).and_then(|a| a.first()) {
+            println!("{}", b);
+        }
+        Some(c)
+    }
+}"##;
+        assert_eq!(errors(text, Some("")), vec![]);
+    }
+
+    #[test]
+    fn closing_delimiters_above_are_not_errors() {
+        let text = "        }\n    }\n    g(a];\n}";
+        assert_eq!(errors(text, None), vec![(2, 7), (3, 0)]);
+        assert_eq!(errors(text, Some("")), vec![(0, 8), (1, 4), (2, 7), (3, 0)]);
+    }
+}